@@ -0,0 +1,194 @@
+//! Times `World::update` on a few canonical scenes. Run with `cargo bench`
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use nalgebra::Vector2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use sandboxed::world::{
+    apply_forces, CellElement, Coordinate, Force, ForceGrid, PackedVelocity, World, WORLD_SIZE,
+};
+use sandboxed::worm::WormSegment;
+
+/// An empty world whose random choices are the same every run
+fn seeded_empty_world() -> World {
+    let mut world = World::with_seed(11);
+    world.clear();
+    world
+}
+
+fn fill(world: &mut World, xs: std::ops::Range<u32>, ys: std::ops::Range<u32>) {
+    for y in ys {
+        for x in xs.clone() {
+            let grain = CellElement::Sand(PackedVelocity::ZERO, None, 0);
+            world.set_cell(&Coordinate::new(x, y), grain);
+        }
+    }
+}
+
+/// A world where every cell is a grain
+fn full_world() -> World {
+    let mut world = seeded_empty_world();
+    fill(&mut world, 0..WORLD_SIZE, 0..WORLD_SIZE);
+    world
+}
+
+/// 50 segments of a worm lying across the middle of the world
+fn worm_forces() -> Vec<Force> {
+    (0..50)
+        .map(|index| WormSegment(Vector2::new(index as f32 * 3.0 - 75.0, 0.0)).force())
+        .collect()
+}
+
+fn update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update");
+
+    let mut world = seeded_empty_world();
+    group.bench_function("empty", |b| b.iter(|| world.update(&[])));
+
+    let mut world = seeded_empty_world();
+    fill(&mut world, 0..WORLD_SIZE, 0..WORLD_SIZE / 2);
+    for _ in 0..10 {
+        world.update(&[]);
+    }
+    group.bench_function("half full of settled sand", |b| {
+        b.iter(|| world.update(&[]))
+    });
+
+    // every tick is timed from the same point, a few ticks into the fall, so the column
+    // never gets the chance to settle
+    group.bench_function("falling column", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut world = seeded_empty_world();
+                fill(
+                    &mut world,
+                    WORLD_SIZE / 2 - 10..WORLD_SIZE / 2 + 10,
+                    WORLD_SIZE / 2..WORLD_SIZE,
+                );
+                for _ in 0..10 {
+                    world.update(&[]);
+                }
+                world
+            },
+            |world| world.update(&[]),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let forces = worm_forces();
+    group.bench_function("full under a worm's forces", |b| {
+        b.iter_batched_ref(
+            full_world,
+            |world| world.update(&forces),
+            BatchSize::LargeInput,
+        )
+    });
+
+    // nothing moves, so the time goes into spreading the heat of the patch
+    let mut world = World::new(500, 500);
+    let mut rng = StdRng::seed_from_u64(5);
+    for y in 125..250 {
+        for x in 125..250 {
+            world.set_temperature(&Coordinate::new(x, y), rng.gen_range(-50.0..700.0));
+        }
+    }
+    group.bench_function(
+        format!(
+            "500x500 heat diffusion on {} threads",
+            rayon::current_num_threads()
+        ),
+        |b| b.iter(|| world.update(&[])),
+    );
+
+    group.finish();
+}
+
+/// A 500x500 world whose top half is sand, a few ticks into falling
+fn falling_half(parallel: bool) -> World {
+    let mut world = World::new(500, 500);
+    world.reseed(11);
+    world.set_parallel(parallel);
+    fill(&mut world, 0..500, 250..500);
+    for _ in 0..5 {
+        world.update(&[]);
+    }
+    world
+}
+
+/// The serial update against the parallel pass, on one thread and on all of them
+fn parallel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("500x500 falling sand");
+    let one_thread = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+
+    group.bench_function("in place", |b| {
+        b.iter_batched_ref(
+            || falling_half(false),
+            |world| world.update(&[]),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("double buffered on one thread", |b| {
+        b.iter_batched_ref(
+            || falling_half(true),
+            |world| one_thread.install(|| world.update(&[])),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function(
+        format!(
+            "double buffered on {} threads",
+            rayon::current_num_threads()
+        ),
+        |b| {
+            b.iter_batched_ref(
+                || falling_half(true),
+                |world| world.update(&[]),
+                BatchSize::LargeInput,
+            )
+        },
+    );
+
+    group.finish();
+}
+
+/// What `update` spends on forces in the full world under a worm, with the grid it sorts them
+/// into and with every cell checking every force
+fn forces(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forces");
+    let world = full_world();
+    let forces = worm_forces();
+    let cells: Vec<Coordinate> = (0..world.height())
+        .flat_map(|y| (0..world.width()).map(move |x| Coordinate::new(x, y)))
+        .collect();
+
+    group.bench_function("every force", |b| {
+        b.iter(|| {
+            for coordinate in cells.iter() {
+                let position = world.position(coordinate);
+                black_box(apply_forces(Vector2::zeros(), &position, forces.iter()));
+            }
+        })
+    });
+
+    group.bench_function("force grid", |b| {
+        b.iter(|| {
+            let grid = ForceGrid::new(&world, &forces);
+            for coordinate in cells.iter() {
+                let position = world.position(coordinate);
+                black_box(apply_forces(
+                    Vector2::zeros(),
+                    &position,
+                    grid.near(coordinate),
+                ));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, update, forces, parallel);
+criterion_main!(benches);
@@ -45,7 +45,8 @@ impl BaseRenderer {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    // line polygons are only used for the debug wireframe
+                    features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
@@ -94,3 +95,27 @@ impl BaseRenderer {
         }
     }
 }
+
+/// A device without a window for tests to draw with. Returns `None` when there's no adapter to
+/// test against, as in CI
+#[cfg(test)]
+pub async fn headless_device(features: wgpu::Features) -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await?;
+
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features: adapter.features() & features,
+                limits: wgpu::Limits::default(),
+                label: None,
+            },
+            None,
+        )
+        .await
+        .ok()?;
+
+    Some((device, queue))
+}
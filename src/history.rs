@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+use crate::world::{CellElement, World};
+
+/// Edits that can be undone and redone. Only what's recorded before each edit ends up here,
+/// so the world stepping on its own never fills it up
+pub struct History {
+    undo: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// most edits that can be undone; the oldest are forgotten past this
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Call right before an edit so `undo` can bring the world back to how it is now; any
+    /// edits that were undone can't be redone afterwards
+    pub fn record(&mut self, world: &World) {
+        self.undo.push_back(Snapshot::take(world));
+        if self.undo.len() > self.capacity {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    /// Puts the world back the way it was before the last edit, returning whether there was
+    /// one to undo
+    pub fn undo(&mut self, world: &mut World) -> bool {
+        let Some(snapshot) = self.undo.pop_back() else {
+            return false;
+        };
+        self.redo.push(Snapshot::take(world));
+        snapshot.restore(world);
+        true
+    }
+
+    /// Puts back the last edit that was undone, returning whether there was one
+    pub fn redo(&mut self, world: &mut World) -> bool {
+        let Some(snapshot) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push_back(Snapshot::take(world));
+        snapshot.restore(world);
+        true
+    }
+
+    /// Forgets every edit, for when the world is replaced by another
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+/// Every cell of a world, run-length encoded since most of a world is long stretches of
+/// the same cell
+struct Snapshot {
+    width: u32,
+    height: u32,
+    /// each cell and how many times in a row it repeats, row by row from the bottom
+    runs: Vec<(CellElement, u32)>,
+}
+
+impl Snapshot {
+    fn take(world: &World) -> Self {
+        let mut runs = Vec::<(CellElement, u32)>::new();
+        for (_, cell) in world.iter_cells() {
+            match runs.last_mut() {
+                Some((last, count)) if *last == cell => *count += 1,
+                _ => runs.push((cell, 1)),
+            }
+        }
+        Self {
+            width: world.width(),
+            height: world.height(),
+            runs,
+        }
+    }
+
+    /// Only the cells are restored; temperatures are left as they are now
+    fn restore(&self, world: &mut World) {
+        assert_eq!(
+            (self.width, self.height),
+            (world.width(), world.height()),
+            "snapshot of a differently sized world"
+        );
+        let cells = self
+            .runs
+            .iter()
+            .flat_map(|&(cell, count)| std::iter::repeat_n(cell, count as usize));
+        for ((_, slot), cell) in world.iter_cells_mut().zip(cells) {
+            *slot = cell;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{History, Snapshot};
+    use crate::world::{CellElement, Coordinate, World};
+
+    #[test]
+    fn undo_and_redo_a_painted_cell() {
+        let mut world = World::new(20, 20);
+        let mut history = History::new(10);
+        let painted = Coordinate::new(5, 5);
+
+        history.record(&world);
+        world.set_cell(&painted, CellElement::Stone);
+
+        assert!(history.undo(&mut world));
+        assert_eq!(world.get_cell(&painted), Some(CellElement::Air));
+        assert!(!history.undo(&mut world));
+
+        assert!(history.redo(&mut world));
+        assert_eq!(world.get_cell(&painted), Some(CellElement::Stone));
+        assert!(!history.redo(&mut world));
+    }
+
+    #[test]
+    fn only_recorded_edits_are_kept() {
+        let mut world = World::new(20, 20);
+        let mut history = History::new(2);
+        for x in 0..3 {
+            history.record(&world);
+            world.set_cell(&Coordinate::new(x, 10), CellElement::Stone);
+            // stepping the world isn't an edit
+            world.update(&[]);
+        }
+
+        assert!(history.undo(&mut world));
+        assert!(history.undo(&mut world));
+        assert!(
+            !history.undo(&mut world),
+            "the oldest edit should be forgotten"
+        );
+        assert_eq!(
+            world.get_cell(&Coordinate::new(0, 10)),
+            Some(CellElement::Stone)
+        );
+        assert_eq!(
+            world.get_cell(&Coordinate::new(1, 10)),
+            Some(CellElement::Air)
+        );
+    }
+
+    #[test]
+    fn snapshots_of_uniform_worlds_are_small() {
+        let mut world = World::new(100, 100);
+        world.set_cell(&Coordinate::new(50, 50), CellElement::Stone);
+        assert_eq!(Snapshot::take(&world).runs.len(), 3);
+    }
+}
@@ -0,0 +1,57 @@
+use wgpu_text::section::{HorizontalAlign, Layout, Section, Text};
+
+/// Lines of overlay text stacked down from the top left corner of the window
+pub struct Hud {
+    lines: Vec<String>,
+    /// height of the text, which is also the height of each line
+    scale: f32,
+}
+
+impl Hud {
+    pub fn new(scale: f32) -> Self {
+        Self {
+            lines: Vec::new(),
+            scale,
+        }
+    }
+
+    /// Adds a line below the ones already added; it shouldn't contain line breaks, or it
+    /// overlaps the next one
+    pub fn add_line(&mut self, text: impl Into<String>) {
+        self.lines.push(text.into());
+    }
+
+    /// A section for every line, one line height below the one before it
+    pub fn sections(&self) -> Vec<Section<'_>> {
+        self.lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                Section::default()
+                    .add_text(Text::new(line).with_scale(self.scale))
+                    .with_screen_position((0.0, index as f32 * self.scale))
+                    .with_layout(Layout::default().h_align(HorizontalAlign::Left))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hud;
+
+    #[test]
+    fn lines_are_stacked_down_from_the_top_left() {
+        let mut hud = Hud::new(16.0);
+        for line in ["60 FPS", "Brush: Sand 5", "", "Sand: 120"] {
+            hud.add_line(line);
+        }
+
+        let sections = hud.sections();
+        assert_eq!(sections.len(), 4);
+        for (index, section) in sections.iter().enumerate() {
+            assert_eq!(section.screen_position, (0.0, index as f32 * 16.0));
+        }
+        assert_eq!(sections[1].text[0].text, "Brush: Sand 5");
+    }
+}
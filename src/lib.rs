@@ -0,0 +1,11 @@
+pub mod base_renderer;
+pub mod history;
+pub mod hud;
+pub mod palette;
+pub mod renderer;
+pub mod save;
+pub mod schedule;
+pub mod simulation;
+pub mod sprite;
+pub mod world;
+pub mod worm;
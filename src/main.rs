@@ -1,31 +1,57 @@
-use std::time::Instant;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
 use nalgebra::Vector2;
 use rand::Rng;
-use wgpu_text::section::{HorizontalAlign, Layout, Section, Text};
 use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
-use worm::Worm;
-
-mod base_renderer;
-mod renderer;
-mod sprite;
-mod world;
-mod worm;
-
-use self::{
-    renderer::Renderer,
-    world::{Coordinate, World, WORLD_SIZE},
+use sandboxed::{
+    history::History,
+    hud::Hud,
+    palette::Palette,
+    renderer::{Camera, Renderer},
+    schedule::Scheduler,
+    simulation::simulate,
+    world::{self, BoundaryMode, Coordinate, ElementKind, World},
+    worm::{Worm, WormColony},
 };
 
-const WORLD_UPDATE_TIME: f32 = 0.1;
-const TARGET_FPS: f64 = 1.0 / 60.0;
+const TARGET_FPS: f64 = 60.0;
+const SIM_STEPS_PER_SECOND: f64 = 10.0;
 const STACK_SIZE: usize = 10_000_000;
-
+const BRUSH_RADIUS: u32 = 5;
+const MAX_BRUSH_RADIUS: u32 = 20;
+/// cells per second the brush sprays while in spray mode
+const SPRAY_RATE: f32 = 200.0;
+/// how much a palette key changes a color channel
+const PALETTE_STEP: u8 = 16;
+/// how much Page Up and Page Down change gravity, in cells per tick squared
+const GRAVITY_STEP: f32 = 0.05;
+/// where Ctrl+S saves the world and Ctrl+O loads it from
+const SAVE_PATH: &str = "world.sand";
+/// how much [ and ] slow down or speed up the simulation
+const SPEED_STEP: f64 = 2.0;
+/// slowest and fastest the simulation can be set to run, relative to real time
+const SPEED_RANGE: (f64, f64) = (1.0 / 16.0, 8.0);
+/// how far around the cursor the middle mouse button blasts cells, in cells
+const EXPLOSION_RADIUS: u32 = 20;
+/// how hard the middle mouse button blasts cells, falling off with the square of the distance
+const EXPLOSION_STRENGTH: f32 = 200.0;
+/// how many edits Ctrl+Z can undo
+const UNDO_LIMIT: usize = 50;
+/// how far the arrow keys pan the camera, in window pixels
+const PAN_STEP: f32 = 20.0;
+/// how far W, A, S and D move the target of a worm driven by hand, in cells
+const WORM_TARGET_STEP: f32 = 10.0;
+/// how much a notch of the scroll wheel zooms in or out
+const ZOOM_STEP: f32 = 1.25;
+/// height of the overlay text, which is also the height of each of its lines
+const TEXT_SCALE: f32 = 16.0;
 
 fn main() {
     let rt = tokio::runtime::Builder::new_multi_thread().thread_stack_size(STACK_SIZE).build().unwrap();
@@ -37,18 +63,39 @@ async fn game() -> Result<(), ()> {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
     let mut last_frame = Instant::now();
-    let mut last_world_step = Instant::now();
+    let mut last_advance = Instant::now();
+    // when the scheduler next wants a frame; the event loop sleeps until then
+    let mut next_frame = Instant::now();
+    let mut scheduler = Scheduler::new(TARGET_FPS, SIM_STEPS_PER_SECOND);
     let mut renderer = Renderer::new(window).await;
     let mut world = World::default();
+    add_fountain(&mut world);
+    let mut cursor_position: Option<PhysicalPosition<f64>> = None;
+    // panning with the right mouse button held
+    let mut dragging = false;
+    let mut modifiers = ModifiersState::empty();
+    let mut brush = Brush::new(BRUSH_RADIUS, SPRAY_RATE);
+    // each brush stroke is recorded as it starts
+    let mut history = History::new(UNDO_LIMIT);
+    let mut palette_element = ElementKind::Sand;
+    let mut selected_element = ElementKind::Sand;
+    let mut selected_worm = 0usize;
+    let mut paused = false;
+    let mut step_once = false;
+    let mut take_screenshot = false;
+    // only recounted after the world steps or is edited, counting every frame would waste the
+    // time
+    let mut counts = world.counts();
     println!("World bytes: {}", std::mem::size_of::<World>());
 
-    let mut worms = vec![Worm::new(
-        7,
+    let mut colony = WormColony::new();
+    colony.spawn(
         Vector2::new(30.0, 30.0),
         Vector2::new(1.0, 1.0).normalize(),
+        7,
         10.0,
         4.0,
-    )];
+    );
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -66,58 +113,316 @@ async fn game() -> Result<(), ()> {
                         },
                     ..
                 } => *control_flow = ControlFlow::Exit,
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } => match key {
+                    // files
+                    VirtualKeyCode::S if modifiers.ctrl() => {
+                        match world.save(Path::new(SAVE_PATH)) {
+                            Ok(()) => println!("Saved world to {SAVE_PATH}"),
+                            Err(e) => eprintln!("{e}"),
+                        }
+                    }
+                    VirtualKeyCode::O if modifiers.ctrl() => {
+                        match World::load(Path::new(SAVE_PATH)) {
+                            Ok(mut loaded) => {
+                                loaded.set_palette(world.palette());
+                                world = loaded;
+                                add_fountain(&mut world);
+                                history.clear();
+                                counts = world.counts();
+                            }
+                            Err(e) => eprintln!("{e}"),
+                        }
+                    }
+                    // edits
+                    VirtualKeyCode::Z if modifiers.ctrl() => {
+                        history.undo(&mut world);
+                        counts = world.counts();
+                    }
+                    VirtualKeyCode::Y if modifiers.ctrl() => {
+                        history.redo(&mut world);
+                        counts = world.counts();
+                    }
+                    // time
+                    VirtualKeyCode::Space => paused = !paused,
+                    VirtualKeyCode::Period if paused => step_once = true,
+                    VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+                        let step = if *key == VirtualKeyCode::LBracket {
+                            1.0 / SPEED_STEP
+                        } else {
+                            SPEED_STEP
+                        };
+                        let (slowest, fastest) = SPEED_RANGE;
+                        scheduler.set_speed((scheduler.speed() * step).clamp(slowest, fastest));
+                    }
+                    // physics
+                    VirtualKeyCode::PageUp => {
+                        world.set_gravity(world.gravity() + Vector2::new(0.0, GRAVITY_STEP))
+                    }
+                    VirtualKeyCode::PageDown => {
+                        world.set_gravity(world.gravity() - Vector2::new(0.0, GRAVITY_STEP))
+                    }
+                    VirtualKeyCode::B => world.set_boundary(match world.boundary() {
+                        BoundaryMode::Solid => BoundaryMode::Wrap,
+                        BoundaryMode::Wrap => BoundaryMode::Void,
+                        BoundaryMode::Void => BoundaryMode::Solid,
+                    }),
+                    // camera
+                    VirtualKeyCode::Left => {
+                        renderer.camera_mut().drag(&Vector2::new(PAN_STEP, 0.0))
+                    }
+                    VirtualKeyCode::Right => {
+                        renderer.camera_mut().drag(&Vector2::new(-PAN_STEP, 0.0))
+                    }
+                    VirtualKeyCode::Up => renderer.camera_mut().drag(&Vector2::new(0.0, -PAN_STEP)),
+                    VirtualKeyCode::Down => {
+                        renderer.camera_mut().drag(&Vector2::new(0.0, PAN_STEP))
+                    }
+                    VirtualKeyCode::Home => *renderer.camera_mut() = Camera::default(),
+                    // brush element
+                    VirtualKeyCode::Key1 => selected_element = ElementKind::Air,
+                    VirtualKeyCode::Key2 => selected_element = ElementKind::Sand,
+                    VirtualKeyCode::Key3 => selected_element = ElementKind::Water,
+                    VirtualKeyCode::Key4 => selected_element = ElementKind::Stone,
+                    VirtualKeyCode::Key5 => selected_element = ElementKind::Wood,
+                    VirtualKeyCode::Key6 => selected_element = ElementKind::Acid,
+                    VirtualKeyCode::Key7 => selected_element = ElementKind::Ice,
+                    VirtualKeyCode::Key8 => selected_element = ElementKind::Plant,
+                    VirtualKeyCode::Key9 => selected_element = ElementKind::Lava,
+                    VirtualKeyCode::Key0 => selected_element = ElementKind::Oil,
+                    // palette editor
+                    VirtualKeyCode::F1 => {
+                        let index = ElementKind::ALL
+                            .iter()
+                            .position(|kind| *kind == palette_element)
+                            .unwrap();
+                        palette_element = ElementKind::ALL[(index + 1) % ElementKind::ALL.len()];
+                    }
+                    VirtualKeyCode::F2 => nudge_color(&mut world, palette_element, 0),
+                    VirtualKeyCode::F3 => nudge_color(&mut world, palette_element, 1),
+                    VirtualKeyCode::F4 => nudge_color(&mut world, palette_element, 2),
+                    VirtualKeyCode::F5 => nudge_color(&mut world, palette_element, 3),
+                    VirtualKeyCode::F7 => renderer.toggle_wireframe(),
+                    // smoother zoomed out, but blurs the grains together
+                    VirtualKeyCode::F8 => renderer.set_filtering(!renderer.filtering()),
+                    VirtualKeyCode::M => renderer.toggle_minimap(),
+                    VirtualKeyCode::G => renderer.toggle_grid(),
+                    VirtualKeyCode::P => brush.toggle_mode(),
+                    VirtualKeyCode::T => renderer.toggle_trails(),
+                    VirtualKeyCode::F12 => take_screenshot = true,
+                    VirtualKeyCode::F11 => {
+                        let night = Palette::night();
+                        world.set_palette(if world.palette() == night {
+                            Palette::default()
+                        } else {
+                            night
+                        });
+                    }
+                    // worm debugging
+                    VirtualKeyCode::Tab if !colony.worms.is_empty() => {
+                        selected_worm = (selected_worm + 1) % colony.worms.len();
+                    }
+                    VirtualKeyCode::F6 => {
+                        if let Some(worm) = colony.worms.get_mut(selected_worm) {
+                            worm.emits_force = !worm.emits_force;
+                        }
+                    }
+                    // driving a worm by hand
+                    VirtualKeyCode::C => {
+                        if let Some(worm) = colony.worms.get_mut(selected_worm) {
+                            if worm.target.is_some() {
+                                worm.target = None;
+                            } else {
+                                let ahead = worm.direction().unwrap_or_else(Vector2::x);
+                                worm.set_target(worm.head.0 + ahead * WORM_TARGET_STEP);
+                            }
+                        }
+                    }
+                    VirtualKeyCode::W
+                    | VirtualKeyCode::A
+                    | VirtualKeyCode::S
+                    | VirtualKeyCode::D => {
+                        let step = match key {
+                            VirtualKeyCode::W => Vector2::new(0.0, WORM_TARGET_STEP),
+                            VirtualKeyCode::A => Vector2::new(-WORM_TARGET_STEP, 0.0),
+                            VirtualKeyCode::S => Vector2::new(0.0, -WORM_TARGET_STEP),
+                            _ => Vector2::new(WORM_TARGET_STEP, 0.0),
+                        };
+                        if let Some(worm) = colony.worms.get_mut(selected_worm) {
+                            if let Some(target) = worm.target {
+                                worm.set_target(target + step);
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+                WindowEvent::ModifiersChanged(state) => modifiers = *state,
                 WindowEvent::Resized(size) => {
                     renderer.resize(*size);
                 }
+                WindowEvent::CursorMoved { position, .. } => {
+                    if let (true, Some(previous)) = (dragging, cursor_position) {
+                        let moved = Vector2::new(position.x - previous.x, previous.y - position.y);
+                        renderer.camera_mut().drag(&moved.cast());
+                    }
+                    cursor_position = Some(*position);
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    cursor_position = None;
+                }
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Left,
+                    ..
+                } => {
+                    brush.set_active(*state == ElementState::Pressed);
+                    if brush.active {
+                        history.record(&world);
+                    }
+                }
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Right,
+                    ..
+                } => {
+                    dragging = *state == ElementState::Pressed;
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Middle,
+                    ..
+                } => {
+                    if let Some(coordinate) = cursor_position.and_then(|position| {
+                        renderer.screen_to_world(Vector2::new(position.x, position.y).cast())
+                    }) {
+                        history.record(&world);
+                        world.explode(&coordinate, EXPLOSION_RADIUS, EXPLOSION_STRENGTH);
+                        counts = world.counts();
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let scrolled = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => *y,
+                        MouseScrollDelta::PixelDelta(position) => position.y as f32,
+                    };
+                    if modifiers.ctrl() {
+                        brush.grow(scrolled.signum() as i32);
+                    } else {
+                        let size = renderer.size();
+                        let cursor = cursor_position.map_or_else(Vector2::zeros, |position| {
+                            screen_position(size, position)
+                        });
+                        renderer
+                            .camera_mut()
+                            .zoom_at(ZOOM_STEP.powf(scrolled.signum()), &cursor);
+                    }
+                }
                 _ => {}
             },
             Event::RedrawRequested(window_id) if window_id == renderer.window().id() => {
                 let time = Instant::now();
+                let delta = time.duration_since(last_advance);
+                last_advance = time;
 
-                if time.duration_since(last_frame).as_secs_f64() < TARGET_FPS {
-                    return;
+                if let Some(coordinate) = cursor_position.and_then(|position| {
+                    renderer.screen_to_world(Vector2::new(position.x, position.y).cast())
+                }) {
+                    brush.paint(
+                        &mut world,
+                        coordinate,
+                        selected_element,
+                        delta.as_secs_f32(),
+                    );
                 }
 
-                let time_since_world_step = time.duration_since(last_world_step).as_secs_f32();
-                if time_since_world_step >= WORLD_UPDATE_TIME {
-                    let coordinate = Coordinate::new(WORLD_SIZE / 2, WORLD_SIZE - 1);
-
-                    if world.get_cell(&coordinate) == Some(world::CellElement::Air) {
-                        world.set_cell(
-                            &coordinate,
-                            world::CellElement::Sand(Vector2::new(
-                                0.0,
-                                rand::thread_rng().gen_range(-2.0..=0.0),
-                            )),
-                        );
-                    }
-
-                    let mut forces = Vec::<world::Force>::new();
-                    for worm in worms.iter_mut() {
-                        worm.step_ai(time_since_world_step);
-                        for segment in worm.segments.iter() {
-                            forces.push(segment.force());
-                        }
-                    }
-
-                    world.update(&forces);
-                    last_world_step = time;
+                let frame = scheduler.advance(delta.as_secs_f64());
+                next_frame = time + Duration::from_secs_f64(scheduler.until_render());
+                *control_flow = ControlFlow::WaitUntil(next_frame);
+                let sim_steps = if paused {
+                    std::mem::take(&mut step_once) as u32
+                } else {
+                    frame.sim_steps
+                };
+                simulate(
+                    &mut world,
+                    &mut colony,
+                    sim_steps,
+                    scheduler.sim_step_time(),
+                );
+                // the brush paints every frame it's held down, paused or not
+                if sim_steps > 0 || brush.active {
+                    counts = world.counts();
                 }
+                colony.worms.retain(Worm::is_alive);
+                selected_worm = selected_worm.min(colony.worms.len().saturating_sub(1));
 
-                const PRECISION: f32 = 10.0;
+                if !frame.render {
+                    return;
+                }
 
-                let fps = (1.0 / time.duration_since(last_frame).as_secs_f32())
+                let mut fps = (1.0 / time.duration_since(last_frame).as_secs_f32())
                     .round()
                     .to_string()
                     + " FPS";
+                if paused {
+                    fps += " PAUSED";
+                }
+                let mut hud = Hud::new(TEXT_SCALE);
+                hud.add_line(fps);
+                hud.add_line(format!("Speed: x{}", scheduler.speed()));
+                hud.add_line(format!(
+                    "Brush: {:?} {} {:?}",
+                    selected_element, brush.radius, brush.mode
+                ));
+                hud.add_line(format!("Gravity: {:.2}", world.gravity().y));
+                hud.add_line(format!("Edges: {:?}", world.boundary()));
+                hud.add_line(format!(
+                    "Palette: {:?} {:?}",
+                    palette_element,
+                    world.element_color(palette_element)
+                ));
+                if let Some(worm) = colony.worms.get(selected_worm) {
+                    hud.add_line(format!(
+                        "Worm {}/{}: {}, forces {}, eaten {}, hunger {:.0}",
+                        selected_worm + 1,
+                        colony.worms.len(),
+                        if worm.target.is_some() {
+                            "driven"
+                        } else {
+                            "hunting"
+                        },
+                        if worm.emits_force { "on" } else { "muted" },
+                        worm.eaten,
+                        worm.hunger
+                    ));
+                }
+                // a gap between the settings and the counts
+                hud.add_line("");
+                for kind in ElementKind::ALL {
+                    hud.add_line(format!("{kind:?}: {}", counts.get(kind)));
+                }
+                let sections = hud.sections();
 
-                // text
-                let section = Section::default()
-                    .add_text(Text::new(&fps))
-                    .with_layout(Layout::default().h_align(HorizontalAlign::Left));
+                if std::mem::take(&mut take_screenshot) {
+                    let seconds = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map_or(0, |elapsed| elapsed.as_secs());
+                    let path = format!("screenshot-{seconds}.png");
+                    match renderer.screenshot(&world, &colony.worms, &sections, Path::new(&path)) {
+                        Ok(()) => println!("Saved screenshot to {path}"),
+                        Err(e) => eprintln!("{e}"),
+                    }
+                }
 
-                match renderer.render(&world, &worms, &[section]) {
-                    Ok(_) => {}
+                match renderer.render(&world, &colony.worms, &sections) {
+                    Ok(_) => world.clear_changed(),
                     // Reconfigure the surface if lost
                     Err(wgpu::SurfaceError::Lost) => renderer.resize(renderer.size()),
                     // The system is out of memory, we should probably quit
@@ -129,9 +434,159 @@ async fn game() -> Result<(), ()> {
                 last_frame = time;
             }
             Event::MainEventsCleared => {
-                renderer.window().request_redraw();
+                if Instant::now() >= next_frame {
+                    renderer.window().request_redraw();
+                } else {
+                    *control_flow = ControlFlow::WaitUntil(next_frame);
+                }
             }
             _ => {}
         }
     });
 }
+
+/// Pours sand from the top center of the world, launching each grain down at up to 2 cells a
+/// tick and up to half a cell a tick to either side
+fn add_fountain(world: &mut World) {
+    let mut fountain = world::Emitter::new(
+        Coordinate::new(world.width() / 2, world.height() - 1),
+        1.0,
+        None,
+    );
+    fountain.pressure = 1.0;
+    fountain.pressure_variance = 1.0;
+    fountain.spread = 0.5;
+    world.add_emitter(fountain);
+}
+
+/// Cycles one RGBA channel of an element's color
+fn nudge_color(world: &mut World, kind: ElementKind, channel: usize) {
+    let mut color = world.element_color(kind);
+    color[channel] = color[channel].wrapping_add(PALETTE_STEP);
+    world.set_element_color(kind, color);
+}
+
+/// A cursor position relative to the center of the window, with y pointing up like the world
+fn screen_position(size: PhysicalSize<u32>, position: PhysicalPosition<f64>) -> Vector2<f32> {
+    Vector2::new(
+        position.x - size.width as f64 / 2.0,
+        size.height as f64 / 2.0 - position.y,
+    )
+    .cast()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BrushMode {
+    /// every cell of the disc is painted at once
+    Fill,
+    /// cells are spawned at random spots in the disc at a steady rate, only into air
+    Spray,
+}
+
+/// Paints a disc of one element around the cursor while the mouse button is held
+struct Brush {
+    active: bool,
+    radius: u32,
+    mode: BrushMode,
+    /// cells per second while spraying
+    rate: f32,
+    /// fractional cells carried over between frames while spraying
+    accumulated: f32,
+}
+
+impl Brush {
+    fn new(radius: u32, rate: f32) -> Self {
+        Self {
+            active: false,
+            radius,
+            mode: BrushMode::Fill,
+            rate,
+            accumulated: 0.0,
+        }
+    }
+
+    fn set_active(&mut self, active: bool) {
+        self.active = active;
+        if !active {
+            self.accumulated = 0.0;
+        }
+    }
+
+    fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            BrushMode::Fill => BrushMode::Spray,
+            BrushMode::Spray => BrushMode::Fill,
+        };
+    }
+
+    /// Changes the radius by `steps`, keeping it within `1..=MAX_BRUSH_RADIUS`
+    fn grow(&mut self, steps: i32) {
+        self.radius = (self.radius as i32 + steps).clamp(1, MAX_BRUSH_RADIUS as i32) as u32;
+    }
+
+    /// Paints around `center` for a frame that took `delta` seconds
+    fn paint(&mut self, world: &mut World, center: Coordinate, kind: ElementKind, delta: f32) {
+        if !self.active {
+            return;
+        }
+
+        let radius = self.radius as i32;
+        let mut rng = rand::thread_rng();
+        match self.mode {
+            BrushMode::Fill => {
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let distance_squared = dx * dx + dy * dy;
+                        if distance_squared > radius * radius {
+                            continue;
+                        }
+                        if let Some(coordinate) = offset_cell(world, &center, dx, dy) {
+                            place(world, &coordinate, kind, &mut rng);
+                        }
+                    }
+                }
+            }
+            BrushMode::Spray => {
+                self.accumulated += self.rate * delta;
+                while self.accumulated >= 1.0 {
+                    self.accumulated -= 1.0;
+
+                    let offset = loop {
+                        let offset = Vector2::new(
+                            rng.gen_range(-radius..=radius),
+                            rng.gen_range(-radius..=radius),
+                        );
+                        if offset.x * offset.x + offset.y * offset.y <= radius * radius {
+                            break offset;
+                        }
+                    };
+                    if let Some(coordinate) = offset_cell(world, &center, offset.x, offset.y) {
+                        if world.get_cell(&coordinate) == Some(world::CellElement::Air) {
+                            place(world, &coordinate, kind, &mut rng);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The cell `dx` and `dy` away from `center`, if it's in the world
+fn offset_cell(world: &World, center: &Coordinate, dx: i32, dy: i32) -> Option<Coordinate> {
+    let x = center.x as i32 + dx;
+    let y = center.y as i32 + dy;
+    if x < 0 || y < 0 {
+        return None;
+    }
+
+    let coordinate = Coordinate::new(x as u32, y as u32);
+    world.in_bounds(&coordinate).then_some(coordinate)
+}
+
+/// Sets `coordinate` to a new cell of `kind`
+fn place(world: &mut World, coordinate: &Coordinate, kind: ElementKind, rng: &mut impl Rng) {
+    world.set_cell(coordinate, world::CellElement::new(kind, rng));
+    if kind == ElementKind::Ice {
+        world.set_temperature(coordinate, world::ICE_TEMPERATURE);
+    }
+}
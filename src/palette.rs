@@ -0,0 +1,60 @@
+use crate::world::ElementKind;
+
+/// Colors the world is drawn with, in sRGB
+#[derive(Copy, Clone, PartialEq)]
+pub struct Palette {
+    colors: [[u8; 4]; ElementKind::ALL.len()],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let mut palette = Self {
+            colors: [[0, 0, 0, 255]; ElementKind::ALL.len()],
+        };
+
+        palette.set_color(ElementKind::Air, [0, 0, 255, 255]);
+        palette.set_color(ElementKind::Sand, [255, 255, 0, 255]);
+        palette.set_color(ElementKind::Water, [40, 140, 255, 255]);
+        palette.set_color(ElementKind::Stone, [128, 128, 128, 255]);
+        palette.set_color(ElementKind::Fire, [255, 100, 0, 255]);
+        palette.set_color(ElementKind::Wood, [115, 75, 40, 255]);
+        palette.set_color(ElementKind::Smoke, [160, 160, 160, 140]);
+        palette.set_color(ElementKind::Acid, [120, 255, 40, 255]);
+        palette.set_color(ElementKind::Steam, [225, 235, 245, 160]);
+        palette.set_color(ElementKind::Ice, [180, 235, 255, 255]);
+        palette.set_color(ElementKind::Plant, [30, 150, 50, 255]);
+        palette.set_color(ElementKind::Lava, [255, 110, 20, 255]);
+        palette.set_color(ElementKind::Oil, [75, 40, 60, 255]);
+
+        palette
+    }
+}
+
+impl Palette {
+    /// Dim colors on a dark sky
+    pub fn night() -> Self {
+        let mut palette = Self::default();
+
+        palette.set_color(ElementKind::Air, [10, 12, 40, 255]);
+        palette.set_color(ElementKind::Sand, [150, 140, 70, 255]);
+        palette.set_color(ElementKind::Water, [20, 60, 140, 255]);
+        palette.set_color(ElementKind::Stone, [70, 70, 80, 255]);
+        palette.set_color(ElementKind::Wood, [70, 45, 25, 255]);
+        palette.set_color(ElementKind::Smoke, [90, 90, 100, 140]);
+        palette.set_color(ElementKind::Acid, [60, 160, 30, 255]);
+        palette.set_color(ElementKind::Steam, [110, 120, 150, 160]);
+        palette.set_color(ElementKind::Ice, [90, 130, 160, 255]);
+        palette.set_color(ElementKind::Plant, [20, 80, 35, 255]);
+        palette.set_color(ElementKind::Oil, [40, 22, 35, 255]);
+
+        palette
+    }
+
+    pub fn color(&self, kind: ElementKind) -> [u8; 4] {
+        self.colors[kind as usize]
+    }
+
+    pub fn set_color(&mut self, kind: ElementKind, color: [u8; 4]) {
+        self.colors[kind as usize] = color;
+    }
+}
@@ -1,26 +1,232 @@
+use std::path::Path;
+
 use nalgebra::Vector2;
 use wgpu_text::font::FontRef;
 use wgpu_text::section::Section;
+use winit::dpi::PhysicalSize;
 use winit::window::Window;
 
 use crate::base_renderer::BaseRenderer;
-use crate::sprite::{Sprite, SpriteBatch, SpriteRenderer};
-use crate::world::{World, WORLD_SIZE};
+use crate::sprite::{Sprite, SpriteBatch, SpriteRenderer, WindowUnifrom};
+use crate::world::{Coordinate, World, WORLD_SIZE};
 use crate::worm::Worm;
 
-const WORLD_TEXTURE_SIZE: wgpu::Extent3d = wgpu::Extent3d {
-    width: WORLD_SIZE,
-    height: WORLD_SIZE,
-    depth_or_array_layers: 1,
-};
+/// furthest the camera zooms out, in window pixels per cell
+pub const MIN_ZOOM: f32 = 0.25;
+/// furthest the camera zooms in, in window pixels per cell
+pub const MAX_ZOOM: f32 = 32.0;
+/// length of the minimap's longer side, in window pixels
+const MINIMAP_SIZE: f32 = 160.0;
+/// gap between the minimap and the corner of the window, in window pixels
+const MINIMAP_MARGIN: f32 = 10.0;
+/// width of the outline of the camera's view on the minimap, in window pixels
+const MINIMAP_OUTLINE: f32 = 1.0;
+/// color of the lines between cells, see-through so the cells still show
+const GRID_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.35];
+/// slowest a cell can move, in cells per update, and still leave a trail
+const TRAIL_MIN_SPEED: f32 = 1.0;
+/// how many updates of movement a trail stretches back over
+const TRAIL_UPDATES: f32 = 3.0;
+/// font the overlay text is drawn in
+const FONT: &[u8] = include_bytes!("../assets/FiraCode-Regular.ttf");
+/// color of the streaks behind fast cells, faint so the cells themselves stand out
+const TRAIL_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.25];
+/// most trails drawn in a frame; past it only the fastest cells leave one
+const MAX_TRAILS: usize = 1024;
+
+/// Which part of the world the window shows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    /// world position drawn at the center of the window, relative to the center of the world
+    pub offset: Vector2<f32>,
+    /// window pixels per cell
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            offset: Vector2::zeros(),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    /// World position under a point given in window pixels from the center, y up
+    pub fn world_position(&self, screen: &Vector2<f32>) -> Vector2<f32> {
+        self.offset + screen / self.zoom
+    }
+
+    /// Moves the world along with a drag of `screen` window pixels
+    pub fn drag(&mut self, screen: &Vector2<f32>) {
+        self.offset -= screen / self.zoom;
+    }
+
+    /// Scales the zoom by `factor`, within `MIN_ZOOM..=MAX_ZOOM`, keeping whatever is under
+    /// the window point `screen` where it is
+    pub fn zoom_at(&mut self, factor: f32, screen: &Vector2<f32>) {
+        let anchor = self.world_position(screen);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = anchor - screen / self.zoom;
+    }
+}
+
+/// An axis-aligned rectangle in window pixels from the center, y up
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ScreenRect {
+    min: Vector2<f32>,
+    max: Vector2<f32>,
+}
+
+impl ScreenRect {
+    fn center(&self) -> Vector2<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    fn size(&self) -> Vector2<f32> {
+        self.max - self.min
+    }
+
+    /// The overlap of both rectangles, `None` if they don't overlap
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        let overlap = Self {
+            min: self.min.sup(&other.min),
+            max: self.max.inf(&other.max),
+        };
+        (overlap.min.x < overlap.max.x && overlap.min.y < overlap.max.y).then_some(overlap)
+    }
+
+    /// Four strips `width` wide running along the inside of the edges
+    fn outline(&self, width: f32) -> [Self; 4] {
+        [
+            Self {
+                max: Vector2::new(self.max.x, self.min.y + width),
+                ..*self
+            },
+            Self {
+                min: Vector2::new(self.min.x, self.max.y - width),
+                ..*self
+            },
+            Self {
+                max: Vector2::new(self.min.x + width, self.max.y),
+                ..*self
+            },
+            Self {
+                min: Vector2::new(self.max.x - width, self.min.y),
+                ..*self
+            },
+        ]
+    }
+
+    /// A sprite covering the rectangle once `camera` has moved it around
+    fn sprite(&self, camera: &Camera, color: [f32; 4]) -> Sprite {
+        Sprite {
+            position: camera.world_position(&self.center()).into(),
+            size: (self.size() / camera.zoom).into(),
+            rotation: 0.0,
+            color,
+        }
+    }
+}
+
+/// A line along every cell edge of a world of `world` cells, one window pixel wide at `zoom`
+fn grid_lines(world: &Vector2<f32>, zoom: f32) -> Vec<Sprite> {
+    let width = 1.0 / zoom;
+    let columns = (0..=world.x as u32).map(|x| Sprite {
+        // the world sprite is centered on the origin with one unit per cell
+        position: [x as f32 - world.x / 2.0, 0.0],
+        size: [width, world.y],
+        rotation: 0.0,
+        color: GRID_COLOR,
+    });
+    let rows = (0..=world.y as u32).map(|y| Sprite {
+        position: [0.0, y as f32 - world.y / 2.0],
+        size: [world.x, width],
+        rotation: 0.0,
+        color: GRID_COLOR,
+    });
+    columns.chain(rows).collect()
+}
+
+/// A streak behind each of `cells`, running back from its center against its velocity, in a
+/// world of `world` cells. Only the `MAX_TRAILS` fastest get one
+fn trails(world: &Vector2<f32>, mut cells: Vec<(Coordinate, Vector2<f32>)>) -> Vec<Sprite> {
+    if cells.len() > MAX_TRAILS {
+        cells.select_nth_unstable_by(MAX_TRAILS, |(_, a), (_, b)| {
+            b.norm_squared().total_cmp(&a.norm_squared())
+        });
+        cells.truncate(MAX_TRAILS);
+    }
+
+    cells
+        .into_iter()
+        .map(|(coordinate, velocity)| {
+            let length = velocity.norm() * TRAIL_UPDATES;
+            let center = Vector2::new(coordinate.x as f32, coordinate.y as f32)
+                + Vector2::repeat(0.5)
+                - world / 2.0
+                - velocity.normalize() * length / 2.0;
+            Sprite {
+                position: center.into(),
+                size: [length, 1.0],
+                rotation: velocity.y.atan2(velocity.x),
+                color: TRAIL_COLOR,
+            }
+        })
+        .collect()
+}
+
+/// Where the whole world is drawn shrunk into the bottom right corner of the window, and which
+/// part of it the camera is showing, if any
+fn minimap_layout(
+    window: &Vector2<f32>,
+    world: &Vector2<f32>,
+    camera: &Camera,
+) -> (ScreenRect, Option<ScreenRect>) {
+    let scale = MINIMAP_SIZE / world.max();
+    let corner = Vector2::new(
+        window.x / 2.0 - MINIMAP_MARGIN,
+        MINIMAP_MARGIN - window.y / 2.0,
+    );
+    let map = ScreenRect {
+        min: corner - Vector2::new(world.x * scale, 0.0),
+        max: corner + Vector2::new(0.0, world.y * scale),
+    };
+
+    let half_view = window / camera.zoom / 2.0;
+    let view = ScreenRect {
+        min: map.center() + (camera.offset - half_view) * scale,
+        max: map.center() + (camera.offset + half_view) * scale,
+    };
+    (map, view.intersection(&map))
+}
 
 pub struct Renderer {
     sprite_renderer: SpriteRenderer,
     base: BaseRenderer,
     world_texture: wgpu::Texture,
+    world_texture_size: wgpu::Extent3d,
     world_bind_group: wgpu::BindGroup,
+    /// the same texture sampled through `linear_sampler`
+    filtered_world_bind_group: wgpu::BindGroup,
+    /// the world's pixels before they're uploaded, kept around so every frame reuses it
+    world_pixels: Vec<u8>,
+    pixel_art_sampler: wgpu::Sampler,
+    linear_sampler: wgpu::Sampler,
+    /// whether the world is drawn through `linear_sampler`
+    filtering: bool,
     circle_bind_group: wgpu::BindGroup,
+    /// a single white pixel, for drawing flat colors
+    white_bind_group: wgpu::BindGroup,
     text_brush: wgpu_text::TextBrush<FontRef<'static>>,
+    camera: Camera,
+    /// whether the whole world is drawn small in a corner
+    minimap: bool,
+    /// whether lines are drawn between the cells
+    grid: bool,
+    /// whether fast cells leave streaks behind them
+    trails: bool,
 }
 
 impl Renderer {
@@ -28,60 +234,197 @@ impl Renderer {
         self.base.size
     }
 
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// The world cell under a point given in window pixels from the top left, or `None` when
+    /// the point is outside the world sprite
+    pub fn screen_to_world(&self, screen: Vector2<f32>) -> Option<Coordinate> {
+        screen_to_cell(
+            &self.camera,
+            self.base.size,
+            self.world_texture_size,
+            &screen,
+        )
+    }
+
+    /// Uploads the rows of the world that changed since they were last drawn, or all of
+    /// them when the texture has to be recreated
     fn load_world(&mut self, world: &World) {
+        let size = world_texture_size(world.width(), world.height());
+        let mut rows = world.changed_rows();
+        if size != self.world_texture_size {
+            self.world_texture = create_world_texture(&self.base.device, size, "world_texture");
+            let view = self
+                .world_texture
+                .create_view(&wgpu::TextureViewDescriptor::default());
+            self.world_bind_group = self.sprite_renderer.create_texture_bind_group(
+                &self.base.device,
+                &self.pixel_art_sampler,
+                &view,
+            );
+            self.filtered_world_bind_group = self.sprite_renderer.create_texture_bind_group(
+                &self.base.device,
+                &self.linear_sampler,
+                &view,
+            );
+            self.world_texture_size = size;
+            rows = Some((0, size.height - 1));
+        }
+        let Some(rows) = rows else {
+            return;
+        };
+        let (origin, layout, extent) = row_upload(size, rows);
+
+        world.write_pixels(&mut self.world_pixels);
         self.base.queue.write_texture(
             // Tells wgpu where to copy the pixel data
             wgpu::ImageCopyTexture {
                 texture: &self.world_texture,
                 mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+                origin,
                 aspect: wgpu::TextureAspect::All,
             },
             // The actual pixel data
-            world.pixels().as_slice(),
-            // The layout of the texture
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * WORLD_SIZE),
-                rows_per_image: std::num::NonZeroU32::new(WORLD_SIZE),
-            },
-            WORLD_TEXTURE_SIZE,
+            &self.world_pixels,
+            layout,
+            extent,
         );
     }
 
     pub fn render(
         &mut self,
         world: &World,
-        worms: &Vec<Worm>,
+        worms: &[Worm],
         text_sections: &[Section],
     ) -> Result<(), wgpu::SurfaceError> {
-        self.load_world(world);
-
         let output = self.base.surface.get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        let mut worm_sprites = Vec::<Sprite>::new();
-        for worm in worms.iter() {
-            worm_sprites.push(position_to_sprite(&worm.head.0));
+        let command_buffers = self.draw_frame(&view, world, worms, text_sections);
+        self.base.queue.submit(command_buffers);
+        output.present();
+
+        Ok(())
+    }
+
+    /// Renders a frame into an offscreen texture the size of the window and writes it to `path`
+    /// as a PNG
+    pub fn screenshot(
+        &mut self,
+        world: &World,
+        worms: &[Worm],
+        text_sections: &[Section],
+        path: &Path,
+    ) -> image::ImageResult<()> {
+        let PhysicalSize { width, height } = self.base.size;
+        let format = self.base.config.format;
+        let texture = self.base.device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // same format as the surface so the pipelines can draw into it
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: Some("screenshot_texture"),
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // buffer copies need every row aligned, so the padding is stripped again after reading
+        let padded_row = padded_bytes_per_row(width);
+        let buffer = self.base.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screenshot Buffer"),
+            size: padded_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut command_buffers = self.draw_frame(&view, world, worms, text_sections);
+        let mut encoder =
+            self.base
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Screenshot Command Encoder"),
+                });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+            },
+            texture.size(),
+        );
+        command_buffers.push(encoder.finish());
+        self.base.queue.submit(command_buffers);
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.base.device.poll(wgpu::Maintain::Wait);
 
-            for segment in worm.segments.iter() {
-                worm_sprites.push(position_to_sprite(&segment.0));
+        let mut pixels = Vec::<u8>::with_capacity((4 * width * height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row as usize) {
+            pixels.extend_from_slice(&row[..4 * width as usize]);
+        }
+        buffer.unmap();
+
+        if matches!(
+            format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
             }
         }
 
+        image::save_buffer(path, &pixels, width, height, image::ColorType::Rgba8)
+    }
+
+    /// Records everything drawn in a frame into `view`
+    fn draw_frame(
+        &mut self,
+        view: &wgpu::TextureView,
+        world: &World,
+        worms: &[Worm],
+        text_sections: &[Section],
+    ) -> Vec<wgpu::CommandBuffer> {
+        self.load_world(world);
+
+        // a diameter of one segment length makes neighbouring segments touch
+        let worm_sprites: Vec<Sprite> = worms
+            .iter()
+            .flat_map(|worm| worm.to_sprites(worm.segment_length))
+            .collect();
+
+        // the background and both sprite batches are recorded into one command buffer, down from
+        // one for the background and one for the sprites; either way it's a single submit
         let mut encoder =
             self.base
                 .device
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Background Command Encoder"),
+                    label: Some("Frame Command Encoder"),
                 });
 
         let render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Background Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
+                view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -97,42 +440,114 @@ impl Renderer {
         });
         drop(render_pass);
 
-        let mut command_buffers = vec![
-            encoder.finish(),
-            self.sprite_renderer.draw(
-                &vec![
-                    SpriteBatch {
-                        sprites: vec![Sprite {
-                            position: [0.0, 0.0],
-                            size: [WORLD_SIZE as f32, WORLD_SIZE as f32],
-                        }],
-                        texture_bind_group: &self.world_bind_group,
-                    },
-                    SpriteBatch {
-                        sprites: worm_sprites,
-                        texture_bind_group: &self.circle_bind_group,
-                    },
-                ],
-                &self.base.device,
-                &self.base.queue,
-                &view,
+        let world_size = Vector2::new(world.width() as f32, world.height() as f32);
+        let world_bind_group = if self.filtering {
+            &self.filtered_world_bind_group
+        } else {
+            &self.world_bind_group
+        };
+        let mut batches = vec![
+            SpriteBatch {
+                sprites: vec![Sprite {
+                    position: [0.0, 0.0],
+                    size: world_size.into(),
+                    rotation: 0.0,
+                    color: Sprite::WHITE,
+                }],
+                texture_bind_group: world_bind_group,
+            },
+            SpriteBatch {
+                sprites: if self.grid {
+                    grid_lines(&world_size, self.camera.zoom)
+                } else {
+                    Vec::new()
+                },
+                texture_bind_group: &self.white_bind_group,
+            },
+            SpriteBatch {
+                sprites: if self.trails {
+                    trails(&world_size, world.fast_cells(TRAIL_MIN_SPEED))
+                } else {
+                    Vec::new()
+                },
+                texture_bind_group: &self.white_bind_group,
+            },
+            SpriteBatch {
+                sprites: worm_sprites,
+                texture_bind_group: &self.circle_bind_group,
+            },
+        ];
+        if self.minimap {
+            // laid out in window pixels, then moved through the camera so the one transform the
+            // sprites share brings them back to the same spot
+            let window = Vector2::new(self.base.size.width as f32, self.base.size.height as f32);
+            let (map, view) = minimap_layout(&window, &world_size, &self.camera);
+            batches.push(SpriteBatch {
+                sprites: vec![map.sprite(&self.camera, Sprite::WHITE)],
+                texture_bind_group: world_bind_group,
+            });
+            batches.push(SpriteBatch {
+                sprites: view
+                    .iter()
+                    .flat_map(|view| view.outline(MINIMAP_OUTLINE))
+                    .map(|edge| edge.sprite(&self.camera, Sprite::WHITE))
+                    .collect(),
+                texture_bind_group: &self.white_bind_group,
+            });
+        }
+
+        self.sprite_renderer.draw(
+            &mut encoder,
+            &batches,
+            &self.base.device,
+            &self.base.queue,
+            view,
+            WindowUnifrom::new(
                 [self.base.size.width as f32, self.base.size.height as f32],
+                self.camera.offset.into(),
+                self.camera.zoom,
             ),
-        ];
+        );
 
-        // text
-        for section in text_sections.iter() {
-            self.text_brush.queue(section);
-            command_buffers.push(
-                self.text_brush
-                    .draw(&self.base.device, &view, &self.base.queue),
-            );
-        }
+        let text = draw_text(
+            &mut self.text_brush,
+            &self.base.device,
+            &self.base.queue,
+            view,
+            text_sections,
+        );
+        vec![encoder.finish(), text]
+    }
 
-        self.base.queue.submit(command_buffers);
-        output.present();
+    pub fn filtering(&self) -> bool {
+        self.filtering
+    }
 
-        Ok(())
+    /// Draws the world smoothed with linear filtering instead of as crisp pixels; that looks
+    /// better zoomed out, but blurs the grains of sand into each other
+    pub fn set_filtering(&mut self, filtering: bool) {
+        self.filtering = filtering;
+    }
+
+    /// Shows or hides lines along the edges of every cell
+    pub fn toggle_grid(&mut self) {
+        self.grid = !self.grid;
+    }
+
+    /// Shows or hides the streaks behind fast moving cells
+    pub fn toggle_trails(&mut self) {
+        self.trails = !self.trails;
+    }
+
+    /// Shows or hides the whole world in a corner, with what the camera sees outlined
+    pub fn toggle_minimap(&mut self) {
+        self.minimap = !self.minimap;
+    }
+
+    /// Switches between filled and wireframe sprites, where supported
+    pub fn toggle_wireframe(&mut self) {
+        self.sprite_renderer
+            .set_wireframe(!self.sprite_renderer.wireframe());
     }
 
     pub fn window(&self) -> &Window {
@@ -142,41 +557,17 @@ impl Renderer {
     pub async fn new(window: Window) -> Self {
         let base = BaseRenderer::new(window).await;
 
-        let world_texture = base.device.create_texture(&wgpu::TextureDescriptor {
-            size: WORLD_TEXTURE_SIZE,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            // Most images are stored using sRGB so we need to reflect that here.
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
-            // COPY_DST means that we want to copy data to this texture
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: Some("world_texture"),
-            // This is the same as with the SurfaceConfig. It
-            // specifies what texture formats can be used to
-            // create TextureViews for this texture. The base
-            // texture format (Rgba8UnormSrgb in this case) is
-            // always supported. Note that using a different
-            // texture format is not supported on the WebGL2
-            // backend.
-            view_formats: &[],
-        });
+        // resized to fit the world the first time one is rendered
+        let world_texture_size = world_texture_size(WORLD_SIZE, WORLD_SIZE);
+        let world_texture = create_world_texture(&base.device, world_texture_size, "world_texture");
 
         // We don't need to configure the texture view much, so let's
         // let wgpu define it.
         let world_texture_view = world_texture.create_view(&wgpu::TextureViewDescriptor::default());
         let circle_texture_view = load_pixel_png(&base.device, &base.queue);
 
-        let pixel_art_sampler = base.device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let pixel_art_sampler = create_sampler(&base.device, wgpu::FilterMode::Nearest);
+        let linear_sampler = create_sampler(&base.device, wgpu::FilterMode::Linear);
 
         let sprite_renderer = SpriteRenderer::new(
             &base.config,
@@ -190,6 +581,11 @@ impl Renderer {
             &pixel_art_sampler,
             &world_texture_view,
         );
+        let filtered_world_bind_group = sprite_renderer.create_texture_bind_group(
+            &base.device,
+            &linear_sampler,
+            &world_texture_view,
+        );
 
         let circle_bind_group = sprite_renderer.create_texture_bind_group(
             &base.device,
@@ -197,19 +593,34 @@ impl Renderer {
             &circle_texture_view,
         );
 
-        let text_brush = wgpu_text::BrushBuilder::using_font_bytes(include_bytes!(
-            "../assets/FiraCode-Regular.ttf"
-        ))
-        .unwrap()
-        .build(&base.device, &base.config);
+        let white_bind_group = sprite_renderer.create_texture_bind_group(
+            &base.device,
+            &pixel_art_sampler,
+            &create_white_texture(&base.device, &base.queue),
+        );
+
+        let text_brush = wgpu_text::BrushBuilder::using_font_bytes(FONT)
+            .unwrap()
+            .build(&base.device, &base.config);
 
         Self {
             text_brush,
             sprite_renderer,
             world_texture,
+            world_texture_size,
             circle_bind_group,
+            white_bind_group,
+            pixel_art_sampler,
+            linear_sampler,
+            filtering: false,
             world_bind_group,
+            filtered_world_bind_group,
+            world_pixels: Vec::new(),
             base,
+            camera: Camera::default(),
+            minimap: false,
+            grid: false,
+            trails: false,
         }
     }
 
@@ -225,6 +636,136 @@ impl Renderer {
     }
 }
 
+/// Draws every section in one go. Each draw uploads whatever is queued into the same vertex
+/// buffer, so drawing section by section would leave every draw showing the last one
+fn draw_text(
+    text_brush: &mut wgpu_text::TextBrush<FontRef<'static>>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    view: &wgpu::TextureView,
+    sections: &[Section],
+) -> wgpu::CommandBuffer {
+    for section in sections {
+        text_brush.queue(section);
+    }
+    text_brush.draw(device, view, queue)
+}
+
+/// Rounds a row of RGBA pixels up to the alignment wgpu requires for texture to buffer copies
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (4 * width).div_ceil(alignment) * alignment
+}
+
+/// Maps window pixels to a cell of the world sprite, which is centered on the origin with one
+/// unit per cell
+fn screen_to_cell(
+    camera: &Camera,
+    window: PhysicalSize<u32>,
+    world: wgpu::Extent3d,
+    screen: &Vector2<f32>,
+) -> Option<Coordinate> {
+    let centered = Vector2::new(
+        screen.x - window.width as f32 / 2.0,
+        window.height as f32 / 2.0 - screen.y,
+    );
+    let position = camera.world_position(&centered)
+        + Vector2::new(world.width as f32, world.height as f32) / 2.0;
+
+    let in_sprite = position.x >= 0.0
+        && position.y >= 0.0
+        && position.x < world.width as f32
+        && position.y < world.height as f32;
+    in_sprite.then(|| Coordinate::new(position.x as u32, position.y as u32))
+}
+
+/// Where the rows from `first_row` to `last_row` of a texture of `size` go, and where they
+/// are in the world's pixels; unlike copies between buffers and textures, writes from the
+/// queue don't need their rows padded to an alignment
+fn row_upload(
+    size: wgpu::Extent3d,
+    (first_row, last_row): (u32, u32),
+) -> (wgpu::Origin3d, wgpu::ImageDataLayout, wgpu::Extent3d) {
+    let row_count = last_row - first_row + 1;
+    let bytes_per_row = 4 * size.width;
+    (
+        wgpu::Origin3d {
+            x: 0,
+            y: first_row,
+            z: 0,
+        },
+        wgpu::ImageDataLayout {
+            offset: (first_row * bytes_per_row) as wgpu::BufferAddress,
+            bytes_per_row: std::num::NonZeroU32::new(bytes_per_row),
+            rows_per_image: std::num::NonZeroU32::new(row_count),
+        },
+        wgpu::Extent3d {
+            height: row_count,
+            ..size
+        },
+    )
+}
+
+fn world_texture_size(width: u32, height: u32) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    }
+}
+
+fn create_world_texture(device: &wgpu::Device, size: wgpu::Extent3d, label: &str) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        // Most images are stored using sRGB so we need to reflect that here.
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
+        // COPY_DST means that we want to copy data to this texture
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some(label),
+        // This is the same as with the SurfaceConfig. It
+        // specifies what texture formats can be used to
+        // create TextureViews for this texture. The base
+        // texture format (Rgba8UnormSrgb in this case) is
+        // always supported. Note that using a different
+        // texture format is not supported on the WebGL2
+        // backend.
+        view_formats: &[],
+    })
+}
+
+fn create_sampler(device: &wgpu::Device, filter: wgpu::FilterMode) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    })
+}
+
+/// A single opaque white pixel, which sprites tint to any flat color
+fn create_white_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::TextureView {
+    let size = world_texture_size(1, 1);
+    let texture = create_world_texture(device, size, "white_texture");
+    queue.write_texture(
+        texture.as_image_copy(),
+        &[255; 4],
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: std::num::NonZeroU32::new(4),
+            rows_per_image: std::num::NonZeroU32::new(1),
+        },
+        size,
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
 fn load_pixel_png(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::TextureView {
     let diffuse_bytes = include_bytes!("../assets/circle.png");
     let diffuse_image = image::load_from_memory(diffuse_bytes).unwrap();
@@ -283,9 +824,297 @@ fn load_pixel_png(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::TextureVi
     diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default())
 }
 
-fn position_to_sprite(position: &Vector2<f32>) -> Sprite {
-    Sprite {
-        position: [position.x, position.y],
-        size: [10.0, 10.0],
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector2;
+
+    use winit::dpi::PhysicalSize;
+
+    use super::{
+        draw_text, grid_lines, minimap_layout, padded_bytes_per_row, row_upload, screen_to_cell,
+        trails, world_texture_size, Camera, ScreenRect, FONT, MAX_TRAILS, MAX_ZOOM, MINIMAP_MARGIN,
+        MINIMAP_SIZE, TRAIL_MIN_SPEED, TRAIL_UPDATES,
+    };
+    use crate::base_renderer::headless_device;
+    use crate::hud::Hud;
+    use crate::world::{CellElement, Coordinate, World};
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_cursor_still() {
+        let mut camera = Camera::default();
+        camera.drag(&Vector2::new(30.0, -10.0));
+        let cursor = Vector2::new(120.0, -45.0);
+        let under_cursor = camera.world_position(&cursor);
+
+        for factor in [2.0, 1.5, 0.25, 1000.0] {
+            camera.zoom_at(factor, &cursor);
+            assert!((camera.world_position(&cursor) - under_cursor).norm() < 1e-3);
+        }
+        assert_eq!(camera.zoom, MAX_ZOOM);
+    }
+
+    #[test]
+    fn dragging_moves_the_world_with_the_cursor() {
+        let mut camera = Camera {
+            zoom: 4.0,
+            ..Camera::default()
+        };
+        let grabbed = camera.world_position(&Vector2::new(10.0, 10.0));
+        camera.drag(&Vector2::new(20.0, 0.0));
+        assert!((camera.world_position(&Vector2::new(30.0, 10.0)) - grabbed).norm() < 1e-5);
+    }
+
+    #[test]
+    fn identity_camera_maps_window_pixels_onto_the_centered_world() {
+        let camera = Camera::default();
+        let window = PhysicalSize::new(800, 600);
+        let world = world_texture_size(200, 100);
+        let cell = |x: f32, y: f32| screen_to_cell(&camera, window, world, &Vector2::new(x, y));
+
+        // the window center is the center of the world, and window y points down
+        assert_eq!(cell(400.0, 300.0), Some(Coordinate::new(100, 50)));
+        assert_eq!(cell(300.0, 349.5), Some(Coordinate::new(0, 0)));
+        assert_eq!(cell(499.5, 250.5), Some(Coordinate::new(199, 99)));
+        assert_eq!(cell(299.5, 300.0), None);
+        assert_eq!(cell(500.0, 300.0), None);
+        assert_eq!(cell(400.0, 350.5), None);
+        assert_eq!(cell(400.0, 249.5), None);
+    }
+
+    #[test]
+    fn minimap_sits_in_the_corner_and_outlines_the_view() {
+        let window = Vector2::new(800.0, 600.0);
+        let world = Vector2::new(300.0, 150.0);
+        let mut camera = Camera::default();
+
+        let (map, view) = minimap_layout(&window, &world, &camera);
+        assert_eq!(map.max.x, 400.0 - MINIMAP_MARGIN);
+        assert_eq!(map.min.y, MINIMAP_MARGIN - 300.0);
+        assert!((map.size() - Vector2::new(MINIMAP_SIZE, MINIMAP_SIZE / 2.0)).norm() < 1e-3);
+        // the whole world fits in the window, so all of it is in view
+        assert_eq!(view, Some(map));
+
+        camera.zoom = 8.0;
+        camera.offset = Vector2::new(30.0, -15.0);
+        let (map, view) = minimap_layout(&window, &world, &camera);
+        let view = view.unwrap();
+        let scale = MINIMAP_SIZE / 300.0;
+        assert!((view.size() - Vector2::new(100.0, 75.0) * scale).norm() < 1e-3);
+        assert!((view.center() - map.center() - camera.offset * scale).norm() < 1e-3);
+
+        // panned right off the world
+        camera.offset = Vector2::new(1000.0, 0.0);
+        assert_eq!(minimap_layout(&window, &world, &camera).1, None);
+    }
+
+    #[test]
+    fn screen_rects_become_sprites_in_the_same_spot_on_screen() {
+        let camera = Camera {
+            offset: Vector2::new(12.0, -7.0),
+            zoom: 2.0,
+        };
+        let rect = ScreenRect {
+            min: Vector2::new(100.0, -40.0),
+            max: Vector2::new(140.0, 0.0),
+        };
+
+        let sprite = rect.sprite(&camera, [1.0; 4]);
+        // undo the shader's transform
+        let center = (Vector2::from(sprite.position) - camera.offset) * camera.zoom;
+        assert!((center - rect.center()).norm() < 1e-4);
+        assert_eq!(sprite.size, [20.0, 20.0]);
+        for edge in rect.outline(1.0) {
+            assert!(edge.size().min() == 1.0);
+        }
+    }
+
+    #[test]
+    fn wide_worlds_upload_whole_rows_of_their_own_width() {
+        let world = World::new(200, 100);
+        let pixels = world.pixels();
+        assert_eq!(pixels.len(), 200 * 100 * 4);
+
+        let size = world_texture_size(world.width(), world.height());
+        let (origin, layout, extent) = row_upload(size, (10, 19));
+        assert_eq!(origin.y, 10);
+        assert_eq!(layout.bytes_per_row.unwrap().get(), 200 * 4);
+        assert_eq!(layout.offset, 10 * 200 * 4);
+        assert_eq!((extent.width, extent.height), (200, 10));
+
+        // every row, the last one included, lies within the pixels
+        let (_, layout, extent) = row_upload(size, (0, 99));
+        let end = layout.offset + (extent.height * layout.bytes_per_row.unwrap().get()) as u64;
+        assert_eq!(end, pixels.len() as u64);
+    }
+
+    #[test]
+    fn grid_lines_fall_on_the_edges_between_cells() {
+        let world = Vector2::new(30.0, 20.0);
+        let camera = Camera {
+            offset: Vector2::new(3.5, -2.25),
+            zoom: 8.0,
+        };
+        let lines = grid_lines(&world, camera.zoom);
+        assert_eq!(lines.len(), 31 + 21);
+
+        let window = PhysicalSize::new(800, 600);
+        let texture = world_texture_size(30, 20);
+        let mut checked = 0;
+        // window pixels, y down, of a world position
+        let to_window = |position: Vector2<f32>| {
+            let screen = (position - camera.offset) * camera.zoom;
+            Vector2::new(screen.x + 400.0, 300.0 - screen.y)
+        };
+        for line in &lines[..31] {
+            assert_eq!(line.size[0] * camera.zoom, 1.0);
+            let edge = to_window(Vector2::new(line.position[0], 0.0));
+            let left = screen_to_cell(&camera, window, texture, &(edge - Vector2::x()));
+            let right = screen_to_cell(&camera, window, texture, &(edge + Vector2::x()));
+            if let (Some(left), Some(right)) = (left, right) {
+                assert_eq!(left.x + 1, right.x);
+                checked += 1;
+            }
+        }
+        for line in &lines[31..] {
+            let edge = to_window(Vector2::new(0.0, line.position[1]));
+            let below = screen_to_cell(&camera, window, texture, &(edge + Vector2::y()));
+            let above = screen_to_cell(&camera, window, texture, &(edge - Vector2::y()));
+            if let (Some(below), Some(above)) = (below, above) {
+                assert_eq!(below.y + 1, above.y);
+                checked += 1;
+            }
+        }
+        // all but the outer edges, which only have cells on one side
+        assert_eq!(checked, 29 + 19);
+    }
+
+    #[test]
+    fn only_fast_cells_leave_trails_behind_them() {
+        let mut world = World::new(20, 20);
+        let sand = |velocity: Vector2<f32>| CellElement::Sand(velocity.into(), None, 0);
+        world.set_cell(&Coordinate::new(5, 10), sand(Vector2::new(0.0, -2.0)));
+        world.set_cell(&Coordinate::new(8, 10), sand(Vector2::new(4.0, 0.0)));
+        world.set_cell(&Coordinate::new(12, 10), sand(Vector2::new(0.0, -0.5)));
+        world.set_cell(&Coordinate::new(15, 10), CellElement::Stone);
+
+        let fast = world.fast_cells(TRAIL_MIN_SPEED);
+        assert_eq!(fast.len(), 2);
+        let sprites = trails(&Vector2::new(20.0, 20.0), fast);
+
+        // falling, so the streak runs up from the grain's center
+        let falling = &sprites[0];
+        assert_eq!(falling.size[0], 2.0 * TRAIL_UPDATES);
+        assert_eq!(falling.position[0], 5.5 - 10.0);
+        assert_eq!(falling.position[1], 10.5 - 10.0 + TRAIL_UPDATES);
+        // twice as fast, twice as long
+        assert_eq!(sprites[1].size[0], 2.0 * falling.size[0]);
+        assert_eq!(sprites[1].position[1], 0.5);
+    }
+
+    #[test]
+    fn only_the_fastest_cells_leave_trails_past_the_limit() {
+        let extra = 10;
+        let cells: Vec<_> = (0..MAX_TRAILS + extra)
+            .map(|index| {
+                let speed = TRAIL_MIN_SPEED + index as f32;
+                (Coordinate::new(index as u32, 0), Vector2::new(speed, 0.0))
+            })
+            .collect();
+
+        let sprites = trails(&Vector2::new(2000.0, 1.0), cells);
+        assert_eq!(sprites.len(), MAX_TRAILS);
+        let slowest = (TRAIL_MIN_SPEED + extra as f32) * TRAIL_UPDATES;
+        assert!(sprites.iter().all(|sprite| sprite.size[0] >= slowest));
+    }
+
+    #[test]
+    fn screenshot_rows_are_padded_to_the_copy_alignment() {
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+        assert_eq!(padded_bytes_per_row(1), 256);
+        assert_eq!(
+            padded_bytes_per_row(800) % wgpu::COPY_BYTES_PER_ROW_ALIGNMENT,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn every_line_of_text_is_drawn() {
+        let Some((device, queue)) = headless_device(wgpu::Features::empty()).await else {
+            return;
+        };
+        let (width, height, scale) = (200, 100, 20.0);
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+        let mut text_brush = wgpu_text::BrushBuilder::using_font_bytes(FONT)
+            .unwrap()
+            .build(&device, &config);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: None,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let padded_row = padded_bytes_per_row(width);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: padded_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut hud = Hud::new(scale);
+        for line in ["Sand: 1", "Water: 2", "Stone: 3"] {
+            hud.add_line(line);
+        }
+        let text = draw_text(&mut text_brush, &device, &queue, &view, &hud.sections());
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+            },
+            texture.size(),
+        );
+        queue.submit([text, encoder.finish()]);
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+        let pixels = slice.get_mapped_range();
+
+        // the texture starts out see-through, so any opaque pixel is text
+        let rows: Vec<_> = pixels.chunks(padded_row as usize).collect();
+        for line in rows.chunks(scale as usize).take(3) {
+            let drawn = line.iter().any(|row| {
+                row[..4 * width as usize]
+                    .chunks(4)
+                    .any(|pixel| pixel[3] > 0)
+            });
+            assert!(drawn);
+        }
     }
 }
@@ -0,0 +1,434 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use nalgebra::Vector2;
+
+use crate::world::{CellElement, Coordinate, PackedVelocity, World};
+
+/// Identifies a serialized world
+const MAGIC: [u8; 4] = *b"SAND";
+/// Bump whenever the layout changes and add a migration to `World::deserialize`
+pub const VERSION: u16 = 3;
+
+const AIR_TAG: u8 = 0;
+const SAND_TAG: u8 = 1;
+const WATER_TAG: u8 = 2;
+const STONE_TAG: u8 = 3;
+const FIRE_TAG: u8 = 4;
+const WOOD_TAG: u8 = 5;
+const SMOKE_TAG: u8 = 6;
+const ACID_TAG: u8 = 7;
+const STEAM_TAG: u8 = 8;
+const ICE_TAG: u8 = 9;
+const PLANT_TAG: u8 = 10;
+const LAVA_TAG: u8 = 11;
+const OIL_TAG: u8 = 12;
+
+#[derive(Debug, PartialEq)]
+pub enum DeserializeError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnknownElement(u8),
+    /// acid with no potency left, which would have been turned into air
+    SpentAcid,
+    UnexpectedEnd,
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a serialized world"),
+            Self::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported world version {version} (latest is {VERSION})"
+                )
+            }
+            Self::UnknownElement(tag) => write!(f, "unknown element tag {tag}"),
+            Self::SpentAcid => write!(f, "acid with no potency left"),
+            Self::UnexpectedEnd => write!(f, "world data ended unexpectedly"),
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Deserialize(DeserializeError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "couldn't read world: {error}"),
+            Self::Deserialize(error) => write!(f, "couldn't load world: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<DeserializeError> for LoadError {
+    fn from(error: DeserializeError) -> Self {
+        Self::Deserialize(error)
+    }
+}
+
+impl World {
+    /// Encodes the cell grid as the magic number, a little endian `VERSION`,
+    /// the world dimensions and then a tag byte plus payload for every cell
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::<u8>::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.width().to_le_bytes());
+        bytes.extend_from_slice(&self.height().to_le_bytes());
+
+        for (_, cell) in self.iter_cells() {
+            match cell {
+                CellElement::Air => bytes.push(AIR_TAG),
+                CellElement::Sand(velocity, color, shade) => {
+                    bytes.push(SAND_TAG);
+                    push_velocity(&mut bytes, velocity);
+                    match color {
+                        Some(color) => {
+                            bytes.push(1);
+                            bytes.extend_from_slice(&color);
+                        }
+                        None => bytes.push(0),
+                    }
+                    bytes.push(shade);
+                }
+                CellElement::Water(velocity) => {
+                    bytes.push(WATER_TAG);
+                    push_velocity(&mut bytes, velocity);
+                }
+                CellElement::Stone => bytes.push(STONE_TAG),
+                CellElement::Fire(lifetime) => {
+                    bytes.push(FIRE_TAG);
+                    bytes.push(lifetime);
+                }
+                CellElement::Wood => bytes.push(WOOD_TAG),
+                CellElement::Smoke(lifetime) => {
+                    bytes.push(SMOKE_TAG);
+                    bytes.push(lifetime);
+                }
+                CellElement::Acid(velocity, potency) => {
+                    bytes.push(ACID_TAG);
+                    push_velocity(&mut bytes, velocity);
+                    bytes.push(potency);
+                }
+                CellElement::Steam => bytes.push(STEAM_TAG),
+                CellElement::Ice => bytes.push(ICE_TAG),
+                CellElement::Plant(height) => {
+                    bytes.push(PLANT_TAG);
+                    bytes.push(height);
+                }
+                CellElement::Lava(velocity) => {
+                    bytes.push(LAVA_TAG);
+                    push_velocity(&mut bytes, velocity);
+                }
+                CellElement::Oil(velocity) => {
+                    bytes.push(OIL_TAG);
+                    push_velocity(&mut bytes, velocity);
+                }
+            }
+        }
+
+        bytes
+    }
+
+    /// Writes `serialize`d bytes to `path`, replacing whatever was there
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.serialize())
+    }
+
+    pub fn load(path: &Path) -> Result<World, LoadError> {
+        Ok(World::deserialize(&std::fs::read(path)?)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<World, DeserializeError> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(DeserializeError::BadMagic);
+        }
+
+        match reader.u16()? {
+            version @ 1..=VERSION => read_world(reader, version),
+            version => Err(DeserializeError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// Velocities are stored unpacked, as two little endian `f32`s
+fn push_velocity(bytes: &mut Vec<u8>, velocity: PackedVelocity) {
+    let velocity = velocity.unpack();
+    bytes.extend_from_slice(&velocity.x.to_le_bytes());
+    bytes.extend_from_slice(&velocity.y.to_le_bytes());
+}
+
+/// Reads the grid of any supported `version`, migrating older layouts as it goes
+///
+/// - 1: sand has no tint
+/// - 2: sand has an optional tint
+/// - 3: sand has a shade
+fn read_world(mut reader: Reader, version: u16) -> Result<World, DeserializeError> {
+    let width = reader.u32()?;
+    let height = reader.u32()?;
+    // every cell takes at least a byte, so don't allocate a world the data can't fill
+    if (reader.bytes.len() as u64) < width as u64 * height as u64 {
+        return Err(DeserializeError::UnexpectedEnd);
+    }
+
+    let mut world = World::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let cell = match reader.u8()? {
+                AIR_TAG => CellElement::Air,
+                SAND_TAG => {
+                    let velocity = reader.velocity()?;
+                    let color = if version >= 2 && reader.u8()? != 0 {
+                        Some(reader.take(4)?.try_into().unwrap())
+                    } else {
+                        None
+                    };
+                    let shade = if version >= 3 { reader.u8()? } else { 0 };
+                    CellElement::Sand(velocity, color, shade)
+                }
+                WATER_TAG => CellElement::Water(reader.velocity()?),
+                STONE_TAG => CellElement::Stone,
+                FIRE_TAG => CellElement::Fire(reader.u8()?),
+                WOOD_TAG => CellElement::Wood,
+                SMOKE_TAG => CellElement::Smoke(reader.u8()?),
+                ACID_TAG => match (reader.velocity()?, reader.u8()?) {
+                    (_, 0) => return Err(DeserializeError::SpentAcid),
+                    (velocity, potency) => CellElement::Acid(velocity, potency),
+                },
+                STEAM_TAG => CellElement::Steam,
+                ICE_TAG => CellElement::Ice,
+                PLANT_TAG => CellElement::Plant(reader.u8()?),
+                LAVA_TAG => CellElement::Lava(reader.velocity()?),
+                OIL_TAG => CellElement::Oil(reader.velocity()?),
+                tag => return Err(DeserializeError::UnknownElement(tag)),
+            };
+            world.set_cell(&Coordinate::new(x, y), cell);
+        }
+    }
+
+    Ok(world)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DeserializeError> {
+        if self.bytes.len() < count {
+            return Err(DeserializeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, DeserializeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DeserializeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, DeserializeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, DeserializeError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn velocity(&mut self) -> Result<PackedVelocity, DeserializeError> {
+        Ok(Vector2::new(self.f32()?, self.f32()?).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector2;
+
+    use super::{DeserializeError, LoadError, VERSION};
+    use crate::world::{CellElement, Coordinate, PackedVelocity, World};
+
+    #[test]
+    fn round_trips_current_version() {
+        let mut world = World::default();
+        world.set_cell(
+            &Coordinate::new(40, 100),
+            CellElement::Sand(Vector2::new(0.5, -1.25).into(), Some([1, 2, 3, 4]), 5),
+        );
+        world.set_cell(
+            &Coordinate::new(41, 100),
+            CellElement::Water(Vector2::new(-2.0, 0.0).into()),
+        );
+        world.set_cell(&Coordinate::new(42, 100), CellElement::Stone);
+        world.set_cell(&Coordinate::new(43, 100), CellElement::Fire(7));
+        world.set_cell(&Coordinate::new(44, 100), CellElement::Wood);
+        world.set_cell(&Coordinate::new(45, 100), CellElement::Smoke(9));
+        world.set_cell(
+            &Coordinate::new(46, 100),
+            CellElement::Acid(Vector2::new(0.0, -3.5).into(), 2),
+        );
+        world.set_cell(&Coordinate::new(47, 100), CellElement::Steam);
+        world.set_cell(&Coordinate::new(48, 100), CellElement::Ice);
+        world.set_cell(&Coordinate::new(49, 100), CellElement::Plant(4));
+
+        let bytes = world.serialize();
+        assert_eq!(u16::from_le_bytes([bytes[4], bytes[5]]), VERSION);
+
+        let loaded = World::deserialize(&bytes).unwrap();
+        assert!(loaded.pixels() == world.pixels());
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(40, 100)),
+            Some(CellElement::Sand(
+                Vector2::new(0.5, -1.25).into(),
+                Some([1, 2, 3, 4]),
+                5
+            ))
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(41, 100)),
+            Some(CellElement::Water(Vector2::new(-2.0, 0.0).into()))
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(42, 100)),
+            Some(CellElement::Stone)
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(43, 100)),
+            Some(CellElement::Fire(7))
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(44, 100)),
+            Some(CellElement::Wood)
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(45, 100)),
+            Some(CellElement::Smoke(9))
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(46, 100)),
+            Some(CellElement::Acid(Vector2::new(0.0, -3.5).into(), 2))
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(47, 100)),
+            Some(CellElement::Steam)
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(48, 100)),
+            Some(CellElement::Ice)
+        );
+        assert_eq!(
+            loaded.get_cell(&Coordinate::new(49, 100)),
+            Some(CellElement::Plant(4))
+        );
+    }
+
+    #[test]
+    fn saves_and_loads_files() {
+        let mut world = World::default();
+        world.set_cell(
+            &Coordinate::new(10, 50),
+            CellElement::Sand(Vector2::new(1.5, -0.75).into(), None, 3),
+        );
+        world.set_cell(
+            &Coordinate::new(11, 50),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+        world.set_cell(&Coordinate::new(12, 50), CellElement::Wood);
+        world.update(&[]);
+
+        let path = std::env::temp_dir().join(format!("sandboxed-{}.sand", std::process::id()));
+        world.save(&path).unwrap();
+        let loaded = World::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert!(loaded.pixels() == world.pixels());
+        for y in 0..world.height() {
+            for x in 0..world.width() {
+                let coordinate = Coordinate::new(x, y);
+                assert_eq!(loaded.get_cell(&coordinate), world.get_cell(&coordinate));
+            }
+        }
+
+        assert!(matches!(World::load(&path), Err(LoadError::Io(_))));
+    }
+
+    #[test]
+    fn migrates_untinted_sand() {
+        let mut bytes = b"SAND".to_vec();
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.push(1);
+        bytes.extend_from_slice(&2.0f32.to_le_bytes());
+        bytes.extend_from_slice(&(-3.0f32).to_le_bytes());
+        bytes.resize(bytes.len() + 5, 0);
+
+        let world = World::deserialize(&bytes).unwrap();
+        assert_eq!((world.width(), world.height()), (3, 2));
+        assert_eq!(
+            world.get_cell(&Coordinate::new(0, 0)),
+            Some(CellElement::Sand(Vector2::new(2.0, -3.0).into(), None, 0))
+        );
+        assert_eq!(
+            world.get_cell(&Coordinate::new(1, 0)),
+            Some(CellElement::Air)
+        );
+    }
+
+    #[test]
+    fn rejects_spent_acid() {
+        let mut world = World::new(2, 1);
+        world.set_cell(
+            &Coordinate::new(0, 0),
+            CellElement::Acid(PackedVelocity::ZERO, 1),
+        );
+        let mut bytes = world.serialize();
+        // the potency is the last byte of the acid cell, which is followed by the air cell
+        let potency = bytes.len() - 2;
+        assert_eq!(bytes[potency], 1);
+        bytes[potency] = 0;
+
+        assert_eq!(
+            World::deserialize(&bytes).err(),
+            Some(DeserializeError::SpentAcid)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut bytes = b"SAND".to_vec();
+        bytes.extend_from_slice(&999u16.to_le_bytes());
+
+        assert_eq!(
+            World::deserialize(&bytes).err(),
+            Some(DeserializeError::UnsupportedVersion(999))
+        );
+        assert_eq!(
+            World::deserialize(b"DUNE").err(),
+            Some(DeserializeError::BadMagic)
+        );
+    }
+}
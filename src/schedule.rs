@@ -0,0 +1,175 @@
+/// most simulation steps run for one frame; past this the sim falls behind real time instead
+/// of taking longer each frame to catch up and never recovering
+pub const MAX_SIM_STEPS_PER_FRAME: u32 = 8;
+
+/// Decides when to render and how many fixed simulation steps to run so the
+/// render rate and the simulation rate can be targeted independently
+pub struct Scheduler {
+    render_fps: f64,
+    sim_sps: f64,
+    /// how much faster than real time the simulation runs, below one for slow motion
+    speed: f64,
+    render_accumulator: f64,
+    sim_accumulator: f64,
+}
+
+/// What to do with the time that passed since the last `Scheduler::advance`
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub sim_steps: u32,
+    pub render: bool,
+}
+
+impl Scheduler {
+    pub fn new(render_fps: f64, sim_sps: f64) -> Self {
+        Self {
+            render_fps,
+            sim_sps,
+            speed: 1.0,
+            render_accumulator: 0.0,
+            sim_accumulator: 0.0,
+        }
+    }
+
+    pub fn render_fps(&self) -> f64 {
+        self.render_fps
+    }
+
+    pub fn set_render_fps(&mut self, render_fps: f64) {
+        self.render_fps = render_fps;
+    }
+
+    pub fn sim_sps(&self) -> f64 {
+        self.sim_sps
+    }
+
+    pub fn set_sim_sps(&mut self, sim_sps: f64) {
+        self.sim_sps = sim_sps;
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Runs the simulation `speed` times as fast as real time without changing how much
+    /// time each step covers, so it takes more steps a frame to fast-forward and a step
+    /// only every few frames in slow motion
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    /// Simulated seconds covered by a single step
+    pub fn sim_step_time(&self) -> f32 {
+        (1.0 / self.sim_sps) as f32
+    }
+
+    /// Seconds from the last `advance` until the next frame is due, so the caller can sleep
+    /// instead of polling
+    pub fn until_render(&self) -> f64 {
+        (1.0 / self.render_fps - self.render_accumulator).max(0.0)
+    }
+
+    /// `delta` is the wall-clock time in seconds since the last call
+    pub fn advance(&mut self, delta: f64) -> Frame {
+        let step_time = 1.0 / self.sim_sps;
+        self.sim_accumulator += delta * self.speed;
+
+        let mut sim_steps = 0;
+        while self.sim_accumulator >= step_time {
+            if sim_steps == MAX_SIM_STEPS_PER_FRAME {
+                // drop the backlog rather than carry it into the next frame
+                self.sim_accumulator %= step_time;
+                break;
+            }
+            self.sim_accumulator -= step_time;
+            sim_steps += 1;
+        }
+
+        let frame_time = 1.0 / self.render_fps;
+        self.render_accumulator += delta;
+
+        let render = self.render_accumulator >= frame_time;
+        if render {
+            // keep the remainder so the average rate hits the target, but never
+            // more than a frame's worth so a hitch doesn't cause a burst of renders
+            self.render_accumulator %= frame_time;
+        }
+
+        Frame { sim_steps, render }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scheduler, MAX_SIM_STEPS_PER_FRAME};
+
+    #[test]
+    fn sim_steps_match_target_rate() {
+        let mut scheduler = Scheduler::new(144.0, 30.0);
+
+        let mut sim_steps = 0;
+        let mut renders = 0;
+        for _ in 0..144 * 60 {
+            let frame = scheduler.advance(1.0 / 144.0);
+            sim_steps += frame.sim_steps;
+            renders += frame.render as u32;
+        }
+
+        assert!((sim_steps as i32 - 30 * 60).abs() <= 1);
+        assert!((renders as i32 - 144 * 60).abs() <= 1);
+    }
+
+    #[test]
+    fn a_long_hitch_caps_the_sim_steps() {
+        let mut scheduler = Scheduler::new(60.0, 30.0);
+        assert_eq!(scheduler.advance(5.0).sim_steps, MAX_SIM_STEPS_PER_FRAME);
+
+        // the missed time is gone, so the next frame runs at the normal rate
+        assert_eq!(scheduler.advance(1.0 / 30.0).sim_steps, 1);
+    }
+
+    #[test]
+    fn waiting_until_the_next_render_renders() {
+        let mut scheduler = Scheduler::new(60.0, 30.0);
+        assert!(!scheduler.advance(0.004).render);
+
+        let wait = scheduler.until_render();
+        assert!((wait - (1.0 / 60.0 - 0.004)).abs() < 1e-9);
+        assert!(scheduler.advance(wait).render);
+        assert!(scheduler.until_render() > 0.0);
+    }
+
+    #[test]
+    fn speed_scales_the_sim_rate() {
+        let steps_in_a_second = |speed| {
+            let mut scheduler = Scheduler::new(60.0, 30.0);
+            scheduler.set_speed(speed);
+            let frames: Vec<_> = (0..60).map(|_| scheduler.advance(1.0 / 60.0)).collect();
+            let renders = frames.iter().filter(|frame| frame.render).count();
+            let steps: u32 = frames.iter().map(|frame| frame.sim_steps).sum();
+            // rendering keeps up whatever the speed
+            assert!(renders >= 59);
+            steps as i32
+        };
+
+        assert!((steps_in_a_second(1.0) - 30).abs() <= 1);
+        assert!((steps_in_a_second(4.0) - 120).abs() <= 1);
+        assert!((steps_in_a_second(0.25) - 7).abs() <= 1);
+    }
+
+    #[test]
+    fn uneven_frames_keep_the_sim_rate() {
+        let mut scheduler = Scheduler::new(60.0, 30.0);
+
+        let mut sim_steps = 0;
+        let mut elapsed = 0.0;
+        let deltas = [0.002, 0.016, 0.05, 0.004, 0.1, 0.007];
+        for delta in deltas.iter().cycle().take(6000) {
+            sim_steps += scheduler.advance(*delta).sim_steps;
+            elapsed += delta;
+        }
+
+        let expected = elapsed * 30.0;
+        assert!((sim_steps as f64 - expected).abs() <= 1.0);
+    }
+}
@@ -0,0 +1,13 @@
+use crate::world::World;
+use crate::worm::WormColony;
+
+/// Runs `ticks` fixed steps of `step_time` seconds, moving and feeding the worms before each
+/// world update so the sand reacts to where they are now
+///
+/// Doesn't touch winit or wgpu, so it can run without a window
+pub fn simulate(world: &mut World, colony: &mut WormColony, ticks: u32, step_time: f32) {
+    for _ in 0..ticks {
+        let forces = colony.step_all(world, step_time);
+        world.update(&forces);
+    }
+}
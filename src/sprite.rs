@@ -1,12 +1,28 @@
 use wgpu::util::DeviceExt;
 use wgpu::*;
 
-const STARTING_LENGTH: u16 = 16;
+const STARTING_LENGTH: u32 = 16;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct WindowUnifrom {
     pub size: [f32; 2],
+    /// world position drawn at the center of the window
+    pub offset: [f32; 2],
+    /// window pixels per world unit
+    pub zoom: f32,
+    _padding: f32,
+}
+
+impl WindowUnifrom {
+    pub fn new(size: [f32; 2], offset: [f32; 2], zoom: f32) -> Self {
+        Self {
+            size,
+            offset,
+            zoom,
+            _padding: 0.0,
+        }
+    }
 }
 
 #[repr(C)]
@@ -14,6 +30,7 @@ pub struct WindowUnifrom {
 struct Vertex {
     position: [f32; 2],
     tex_coords: [f32; 2],
+    color: [f32; 4],
 }
 
 impl Vertex {
@@ -32,13 +49,19 @@ impl Vertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[[f32; 2]; 2]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
-fn indices(sprites: u16) -> Vec<u16> {
-    let mut indicies = Vec::<u16>::new();
+/// 32 bit, since 16 bit indices run out at 16384 sprites
+fn indices(sprites: u32) -> Vec<u32> {
+    let mut indicies = Vec::<u32>::new();
 
     for i in 0..sprites {
         let offset = i * 4;
@@ -53,49 +76,56 @@ fn indices(sprites: u16) -> Vec<u16> {
     indicies
 }
 
+/// Rounds up so a batch that grows a little every frame doesn't recreate the buffers each time
+fn grown_length(sprite_count: u32) -> u32 {
+    sprite_count.next_power_of_two()
+}
+
 pub struct SpriteRenderer {
     vertex_buffer: Buffer,
     index_buffer: Buffer,
     window_buffer: Buffer,
     pipeline: RenderPipeline,
+    /// only available with `Features::POLYGON_MODE_LINE`
+    wireframe_pipeline: Option<RenderPipeline>,
+    wireframe: bool,
     window_bind_group: BindGroup,
     texture_bind_group_layout: BindGroupLayout,
-    length: u16,
+    length: u32,
 }
 
 pub struct Sprite {
     pub position: [f32; 2],
     pub size: [f32; 2],
+    /// counter-clockwise around the center, in radians
+    pub rotation: f32,
+    /// multiplied with the texture, so `Sprite::WHITE` draws it unchanged
+    pub color: [f32; 4],
 }
 
 impl Sprite {
+    pub const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
     fn vertices(&self) -> [Vertex; 4] {
         let half_width = self.size[0] / 2.0;
         let half_height = self.size[1] / 2.0;
+        let (sin, cos) = self.rotation.sin_cos();
 
-        let left = self.position[0] - half_width;
-        let right = self.position[0] + half_width;
-
-        let bottom = self.position[1] - half_height;
-        let top = self.position[1] + half_height;
+        // rotated here rather than in the shader so the vertex layout stays the same
+        let corner = |x: f32, y: f32, tex_coords: [f32; 2]| Vertex {
+            position: [
+                self.position[0] + x * cos - y * sin,
+                self.position[1] + x * sin + y * cos,
+            ],
+            tex_coords,
+            color: self.color,
+        };
 
         [
-            Vertex {
-                position: [left, bottom],
-                tex_coords: [0.0, 0.0],
-            },
-            Vertex {
-                position: [right, bottom],
-                tex_coords: [1.0, 0.0],
-            },
-            Vertex {
-                position: [right, top],
-                tex_coords: [1.0, 1.0],
-            },
-            Vertex {
-                position: [left, top],
-                tex_coords: [0.0, 1.0],
-            },
+            corner(-half_width, -half_height, [0.0, 0.0]),
+            corner(half_width, -half_height, [1.0, 0.0]),
+            corner(half_width, half_height, [1.0, 1.0]),
+            corner(-half_width, half_height, [0.0, 1.0]),
         ]
     }
 }
@@ -105,15 +135,15 @@ pub struct SpriteBatch<'a> {
     pub texture_bind_group: &'a BindGroup,
 }
 
-fn create_vertex_buffer(sprite_count: u16, device: &Device) -> wgpu::Buffer {
+fn create_vertex_buffer(sprite_count: u32, device: &Device) -> wgpu::Buffer {
     device.create_buffer_init(&util::BufferInitDescriptor {
         label: Some("Sprite Vertex Buffer"),
-        contents: &vec![0u8; std::mem::size_of::<Vertex>() * (sprite_count * 4) as usize],
+        contents: &vec![0u8; std::mem::size_of::<Vertex>() * sprite_count as usize * 4],
         usage: wgpu::BufferUsages::VERTEX | BufferUsages::COPY_DST,
     })
 }
 
-fn create_index_buffer(sprite_count: u16, device: &Device) -> wgpu::Buffer {
+fn create_index_buffer(sprite_count: u32, device: &Device) -> wgpu::Buffer {
     let index_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
         label: Some("Sprite Index Buffer"),
         contents: bytemuck::cast_slice(&indices(sprite_count)),
@@ -123,6 +153,59 @@ fn create_index_buffer(sprite_count: u16, device: &Device) -> wgpu::Buffer {
     index_buffer
 }
 
+fn create_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    format: TextureFormat,
+    polygon_mode: PolygonMode,
+) -> RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::descriptor()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Anything other than Fill requires Features::POLYGON_MODE_LINE or POLYGON_MODE_POINT
+            polygon_mode,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
 impl SpriteRenderer {
     pub fn new(
         config: &SurfaceConfiguration,
@@ -176,9 +259,7 @@ impl SpriteRenderer {
             });
 
         // window size
-        let window_uniform = WindowUnifrom {
-            size: [window_width, window_height],
-        };
+        let window_uniform = WindowUnifrom::new([window_width, window_height], [0.0, 0.0], 1.0);
 
         let window_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Window Buffer"),
@@ -201,50 +282,27 @@ impl SpriteRenderer {
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::descriptor()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::One,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        });
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            config.format,
+            wgpu::PolygonMode::Fill,
+        );
+
+        // wireframes are a debugging aid, so quietly go without them where unsupported
+        let wireframe_pipeline = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+            .then(|| {
+                create_pipeline(
+                    device,
+                    &pipeline_layout,
+                    &shader,
+                    config.format,
+                    wgpu::PolygonMode::Line,
+                )
+            });
 
         Self {
             texture_bind_group_layout,
@@ -252,11 +310,22 @@ impl SpriteRenderer {
             vertex_buffer,
             index_buffer,
             pipeline,
+            wireframe_pipeline,
+            wireframe: false,
             window_bind_group,
             window_buffer,
         }
     }
 
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    /// Draws sprite outlines instead of filled quads, if the device supports it
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        self.wireframe = wireframe && self.wireframe_pipeline.is_some();
+    }
+
     pub fn create_texture_bind_group(
         &self,
         device: &Device,
@@ -279,24 +348,19 @@ impl SpriteRenderer {
         })
     }
 
+    /// Records every batch into a single render pass on `encoder`, drawn in order on top of
+    /// whatever `view` already holds
     pub fn draw(
         &mut self,
-        sprite_batches: &Vec<SpriteBatch>,
+        encoder: &mut CommandEncoder,
+        sprite_batches: &[SpriteBatch],
         device: &Device,
         queue: &Queue,
         view: &TextureView,
-        window_size: [f32; 2],
-    ) -> CommandBuffer {
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Sprite Command Encoder"),
-        });
-
+        window: WindowUnifrom,
+    ) {
         // this doesn't need to write every frame, but I don't want to overcomplicate things
-        queue.write_buffer(
-            &self.window_buffer,
-            0,
-            bytemuck::cast_slice(&[WindowUnifrom { size: window_size }]),
-        );
+        queue.write_buffer(&self.window_buffer, 0, bytemuck::cast_slice(&[window]));
 
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Sprite Render Pass"),
@@ -322,14 +386,21 @@ impl SpriteRenderer {
                 vertices.push(sprite_vertices[3]);
             }
         }
-        let sprite_count = vertices.len() as u16 / 4;
+        let sprite_count = (vertices.len() / 4) as u32;
 
         if self.length < sprite_count {
-            self.resize(sprite_count, device);
+            self.resize(grown_length(sprite_count), device);
         }
 
-        render_pass.set_pipeline(&self.pipeline);
+        match &self.wireframe_pipeline {
+            Some(wireframe_pipeline) if self.wireframe => {
+                render_pass.set_pipeline(wireframe_pipeline)
+            }
+            _ => render_pass.set_pipeline(&self.pipeline),
+        }
         render_pass.set_bind_group(1, &self.window_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
 
         // can only write to buffer once a frame
         queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
@@ -339,18 +410,13 @@ impl SpriteRenderer {
             let sprite_indicies = batch.sprites.len() as u32 * 6;
 
             render_pass.set_bind_group(0, batch.texture_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
             render_pass.draw_indexed(offset..offset + sprite_indicies, 0, 0..1);
 
             offset += sprite_indicies;
         }
-
-        drop(render_pass);
-        encoder.finish()
     }
 
-    pub fn resize(&mut self, sprite_count: u16, device: &Device) {
+    pub fn resize(&mut self, sprite_count: u32, device: &Device) {
         if sprite_count == 0 {
             return;
         }
@@ -359,3 +425,149 @@ impl SpriteRenderer {
         self.length = sprite_count;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        grown_length, indices, Sprite, SpriteBatch, SpriteRenderer, WindowUnifrom, STARTING_LENGTH,
+    };
+    use crate::base_renderer::headless_device;
+
+    fn texture_view(device: &wgpu::Device, size: u32) -> wgpu::TextureView {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                label: None,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn config() -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: 100,
+            height: 100,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn wireframe_pipeline_follows_device_features() {
+        let Some((device, _queue)) = headless_device(wgpu::Features::POLYGON_MODE_LINE).await
+        else {
+            return;
+        };
+
+        let mut sprite_renderer = SpriteRenderer::new(&config(), &device, 100.0, 100.0);
+        let supported = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE);
+        assert_eq!(sprite_renderer.wireframe_pipeline.is_some(), supported);
+
+        sprite_renderer.set_wireframe(true);
+        assert_eq!(sprite_renderer.wireframe(), supported);
+    }
+
+    #[test]
+    fn quarter_turn_moves_top_right_corner_to_top_left() {
+        let sprite = |rotation| Sprite {
+            position: [5.0, -3.0],
+            size: [4.0, 4.0],
+            rotation,
+            color: Sprite::WHITE,
+        };
+
+        let top_left = sprite(0.0).vertices()[3].position;
+        let rotated_top_right = sprite(std::f32::consts::FRAC_PI_2).vertices()[2].position;
+        assert!((rotated_top_right[0] - top_left[0]).abs() < 1e-5);
+        assert!((rotated_top_right[1] - top_left[1]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn buffers_grow_to_the_next_power_of_two() {
+        assert_eq!(grown_length(STARTING_LENGTH + 1), 32);
+        assert_eq!(grown_length(100), 128);
+        assert_eq!(grown_length(128), 128);
+        // past where 16 bit lengths and indices ran out
+        assert_eq!(grown_length(16_385), 32_768);
+        assert_eq!(grown_length(40_000), 65_536);
+    }
+
+    #[test]
+    fn indices_reach_past_sixteen_bits() {
+        let sprites = u16::MAX as u32 / 4 + 2;
+        let indices = indices(sprites);
+        assert_eq!(indices.len(), sprites as usize * 6);
+        let last = (sprites - 1) * 4;
+        assert_eq!(
+            indices[indices.len() - 6..],
+            [last, last + 1, last + 2, last + 2, last + 3, last]
+        );
+        assert!(last + 3 > u16::MAX as u32);
+    }
+
+    /// Draws `count` sprites in one batch, returning the length the buffers grew to, or `None`
+    /// without an adapter
+    async fn draw_sprites(count: u32) -> Option<u32> {
+        let (device, queue) = headless_device(wgpu::Features::empty()).await?;
+
+        let mut sprite_renderer = SpriteRenderer::new(&config(), &device, 100.0, 100.0);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        let texture_bind_group =
+            sprite_renderer.create_texture_bind_group(&device, &sampler, &texture_view(&device, 1));
+        let target = texture_view(&device, 100);
+
+        let sprites = (0..count)
+            .map(|i| Sprite {
+                position: [(i % 100) as f32 - 50.0, 0.0],
+                size: [1.0, 1.0],
+                rotation: 0.0,
+                color: Sprite::WHITE,
+            })
+            .collect();
+        let mut encoder = device.create_command_encoder(&Default::default());
+        sprite_renderer.draw(
+            &mut encoder,
+            &[SpriteBatch {
+                sprites,
+                texture_bind_group: &texture_bind_group,
+            }],
+            &device,
+            &queue,
+            &target,
+            WindowUnifrom::new([100.0, 100.0], [0.0, 0.0], 1.0),
+        );
+        queue.submit([encoder.finish()]);
+        device.poll(wgpu::Maintain::Wait);
+
+        Some(sprite_renderer.length)
+    }
+
+    #[tokio::test]
+    async fn draws_more_sprites_than_the_starting_length() {
+        if let Some(length) = draw_sprites(100).await {
+            assert_eq!(length, 128);
+        }
+    }
+
+    #[tokio::test]
+    async fn draws_more_sprites_than_sixteen_bit_indices_reach() {
+        if let Some(length) = draw_sprites(u16::MAX as u32 / 4 + 2).await {
+            assert_eq!(length, 32_768);
+        }
+    }
+}
@@ -1,6 +1,10 @@
 // Vertex shader
 struct WindowUniform {
     size: vec2<f32>,
+    // world position at the center of the window
+    offset: vec2<f32>,
+    // window pixels per world unit
+    zoom: f32,
 };
 
 @group(1) @binding(0)
@@ -9,11 +13,13 @@ var<uniform> window: WindowUniform;
 struct VertexInput {
     @location(0) position: vec2<f32>,
 	@location(1) tex_coords: vec2<f32>,
+	@location(2) color: vec4<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
 	@location(0) tex_coords: vec2<f32>,
+	@location(1) color: vec4<f32>,
 };
 
 @vertex
@@ -21,8 +27,9 @@ fn vs_main(
 	model: VertexInput,
 ) -> VertexOutput {
     var out: VertexOutput;
-    out.clip_position = vec4<f32>((model.position + window.size / 2.0) / window.size * 2.0 - 1.0, 0.5, 1.0);
+    out.clip_position = vec4<f32>((model.position - window.offset) * window.zoom / window.size * 2.0, 0.5, 1.0);
 	out.tex_coords = model.tex_coords;
+	out.color = model.color;
     return out;
 }
 
@@ -35,6 +42,6 @@ var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.color;
 }
 
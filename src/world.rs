@@ -1,17 +1,108 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
 use nalgebra::Vector2;
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::palette::Palette;
 
+/// width and height of the default world
 pub const WORLD_SIZE: u32 = 300;
-pub const HALF_WORLD_SIZE: u32 = WORLD_SIZE / 2;
+/// gravity a world starts with, in cells per tick squared
 pub const GRAVITY: Vector2<f32> = Vector2::new(0.0, -0.2);
-const AIR_FRICTION: f32 = 0.25;
+/// air friction a world starts with, in cells per tick squared
+pub const AIR_FRICTION: f32 = 0.25;
+/// fastest a cell can move, in cells per tick, however many forces pull on it
+pub const MAX_VELOCITY: f32 = 30.0;
+/// steps per cell per tick a packed velocity is stored in; `MAX_VELOCITY` times this has to
+/// fit in an `i16`
+const VELOCITY_SCALE: f32 = 1000.0;
+/// how many times a grain may bounce between the same two cells before it's forced to rest
+const OSCILLATION_LIMIT: u8 = 4;
+/// ticks a grain can stay put between bounces and still count as oscillating
+const OSCILLATION_WINDOW: u8 = 4;
+/// keeps forces and gravity wells from flinging grains that get close to their center
+const MIN_FALLOFF_DISTANCE_SQUARED: f32 = 1.0;
+/// width and height of the buckets forces are sorted into, in cells
+const FORCE_BUCKET_SIZE: u32 = 16;
+/// ticks a fire burns for, including the ones it lights
+pub const FIRE_LIFETIME: u8 = 30;
+/// chance a fire drifts up a cell each tick
+const FIRE_RISE_CHANCE: f64 = 0.3;
+/// how much darker than the palette color a grain of sand may be
+pub const MAX_SAND_SHADE: u8 = 40;
+/// ticks the smoke a fire leaves behind lingers for
+pub const SMOKE_LIFETIME: u8 = 60;
+/// cells a drop of acid dissolves before it's used up
+pub const ACID_POTENCY: u8 = 3;
+/// chance acid eats through each soluble cell it touches every tick
+const ACID_DISSOLVE_CHANCE: f64 = 0.2;
+/// cells a plant grows above its seed before it stops
+pub const PLANT_MAX_HEIGHT: u8 = 16;
+/// chance a plant touching water grows a cell each tick
+const PLANT_GROWTH_CHANCE: f64 = 0.05;
+/// temperature every cell of a new world starts at, in degrees Celsius
+pub const AMBIENT_TEMPERATURE: f32 = 20.0;
+/// a burning cell is at least this hot
+pub const FIRE_TEMPERATURE: f32 = 600.0;
+/// share of the temperature difference to each neighbor a cell evens out every tick; above
+/// 0.25 a cell would give away more heat than it has
+const HEAT_DIFFUSION: f32 = 0.1;
+/// once no cell's temperature changes by more than this in a tick, diffusion stops until
+/// something heats up or cools down
+const HEAT_SETTLED: f32 = 0.001;
+/// water hotter than this turns into steam
+pub const BOILING_TEMPERATURE: f32 = 100.0;
+/// steam cooler than this turns back into water; lower than boiling so a drop at the
+/// boiling point doesn't flicker between the two
+pub const CONDENSATION_TEMPERATURE: f32 = 80.0;
+/// ice warmer than this melts into water
+pub const MELTING_TEMPERATURE: f32 = 0.0;
+/// water colder than this freezes into ice; lower than melting for the same reason
+/// condensation is lower than boiling
+pub const FREEZING_TEMPERATURE: f32 = -5.0;
+/// temperature freshly placed ice starts at, so it doesn't melt straight away
+pub const ICE_TEMPERATURE: f32 = -20.0;
+/// lava keeps itself at least this hot
+pub const LAVA_TEMPERATURE: f32 = 1200.0;
+/// lava cooler than this sets into stone
+pub const LAVA_SOLIDIFYING_TEMPERATURE: f32 = 700.0;
+/// heat each drop of water touching lava draws out of it every tick, and boils away with
+const LAVA_QUENCHING: f32 = 200.0;
+/// chance lava flows each tick; it's thicker than water, so it only moves some of the time
+const LAVA_FLOW_CHANCE: f64 = 0.5;
 
 pub type Coordinate = Vector2<u32>;
 
+/// A velocity stored as two fixed-point numbers, which takes half the space of a
+/// `Vector2<f32>` and keeps cells small. Only holds speeds up to `MAX_VELOCITY`, to the
+/// nearest thousandth of a cell per tick
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct PackedVelocity([i16; 2]);
+
+impl PackedVelocity {
+    pub const ZERO: Self = Self([0, 0]);
+
+    pub fn pack(velocity: Vector2<f32>) -> Self {
+        let pack = |component: f32| (component * VELOCITY_SCALE).round() as i16;
+        Self([pack(velocity.x), pack(velocity.y)])
+    }
+
+    pub fn unpack(self) -> Vector2<f32> {
+        Vector2::new(self.0[0] as f32, self.0[1] as f32) / VELOCITY_SCALE
+    }
+}
+
+impl From<Vector2<f32>> for PackedVelocity {
+    fn from(velocity: Vector2<f32>) -> Self {
+        Self::pack(velocity)
+    }
+}
+
 trait Difference<T> {
     fn difference(&self, other: &Self) -> T;
-    fn position(&self) -> Vector2<f32>;
-    fn in_bounds(&self) -> bool;
 }
 
 impl Difference<Vector2<i32>> for Coordinate {
@@ -21,17 +112,6 @@ impl Difference<Vector2<i32>> for Coordinate {
             self.y as i32 - other.y as i32,
         )
     }
-
-    fn position(&self) -> Vector2<f32> {
-        Vector2::new(
-            self.x as f32 - HALF_WORLD_SIZE as f32,
-            self.y as f32 - HALF_WORLD_SIZE as f32,
-        )
-    }
-
-    fn in_bounds(&self) -> bool {
-        self.x < WORLD_SIZE && self.y < WORLD_SIZE
-    }
 }
 
 trait Unit {
@@ -52,7 +132,7 @@ impl Unit for Vector2<i32> {
         } else if Self::new(0, -1) == *self {
             Some([Self::new(-1, -1), Self::new(1, -1)])
         } else if Self::new(-1, 0) == *self {
-            Some([Self::new(-1, -1), Self::new(-1, -1)])
+            Some([Self::new(-1, -1), Self::new(-1, 1)])
         } else if Self::new(-1, -1) == *self {
             Some([Self::new(-1, 0), Self::new(0, -1)])
         } else if Self::new(1, -1) == *self {
@@ -63,288 +143,4061 @@ impl Unit for Vector2<i32> {
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum CellElement {
     Air,
-    Sand(Vector2<f32>),
+    /// velocity, an optional tint overriding the palette and how much darker the grain is
+    Sand(PackedVelocity, Option<[u8; 4]>, u8),
+    /// falls like sand but spreads sideways once it lands
+    Water(PackedVelocity),
+    /// never moves
+    Stone,
+    /// ticks left until it burns out
+    Fire(u8),
+    /// never moves, but burns
+    Wood,
+    /// ticks left until it clears
+    Smoke(u8),
+    /// flows like water, with the number of cells it can still dissolve
+    Acid(PackedVelocity, u8),
+    /// boiled water, rises until it cools down enough to condense
+    Steam,
+    /// frozen water, never moves until it melts
+    Ice,
+    /// never moves, but grows upward by drinking the water it touches; the payload is how
+    /// many cells above its seed it is
+    Plant(u8),
+    /// flows like a thick liquid, keeps itself hot and sets into stone once water cools it
+    Lava(PackedVelocity),
+    /// flows like water but lighter, so it floats on top of it, and burns
+    Oil(PackedVelocity),
 }
 
 impl CellElement {
-    fn push_color(&self, pixels: &mut Vec<u8>) {
+    /// A freshly placed cell of `kind`, at rest
+    pub fn new(kind: ElementKind, rng: &mut impl Rng) -> Self {
+        match kind {
+            ElementKind::Air => Self::Air,
+            ElementKind::Sand => Self::Sand(PackedVelocity::ZERO, None, sand_shade(rng)),
+            ElementKind::Water => Self::Water(PackedVelocity::ZERO),
+            ElementKind::Stone => Self::Stone,
+            ElementKind::Fire => Self::Fire(FIRE_LIFETIME),
+            ElementKind::Wood => Self::Wood,
+            ElementKind::Smoke => Self::Smoke(SMOKE_LIFETIME),
+            ElementKind::Acid => Self::Acid(PackedVelocity::ZERO, ACID_POTENCY),
+            ElementKind::Steam => Self::Steam,
+            ElementKind::Ice => Self::Ice,
+            ElementKind::Plant => Self::Plant(0),
+            ElementKind::Lava => Self::Lava(PackedVelocity::ZERO),
+            ElementKind::Oil => Self::Oil(PackedVelocity::ZERO),
+        }
+    }
+
+    pub fn kind(&self) -> ElementKind {
         match self {
-            Self::Air => {
-                pixels.push(0);
-                pixels.push(0);
-                pixels.push(255);
-                pixels.push(255);
-            }
-            Self::Sand(..) => {
-                pixels.push(255);
-                pixels.push(255);
-                pixels.push(0);
-                pixels.push(255);
-            }
+            Self::Air => ElementKind::Air,
+            Self::Sand(..) => ElementKind::Sand,
+            Self::Water(..) => ElementKind::Water,
+            Self::Stone => ElementKind::Stone,
+            Self::Fire(..) => ElementKind::Fire,
+            Self::Wood => ElementKind::Wood,
+            Self::Smoke(..) => ElementKind::Smoke,
+            Self::Acid(..) => ElementKind::Acid,
+            Self::Steam => ElementKind::Steam,
+            Self::Ice => ElementKind::Ice,
+            Self::Plant(..) => ElementKind::Plant,
+            Self::Lava(..) => ElementKind::Lava,
+            Self::Oil(..) => ElementKind::Oil,
         }
     }
-}
 
-#[allow(dead_code)]
-pub struct World {
-    cells: [[CellElement; WORLD_SIZE as usize]; WORLD_SIZE as usize],
-}
+    /// Velocity of cells that move, `None` for the ones that don't
+    pub fn velocity(&self) -> Option<Vector2<f32>> {
+        match self {
+            Self::Air
+            | Self::Stone
+            | Self::Fire(..)
+            | Self::Wood
+            | Self::Smoke(..)
+            | Self::Steam
+            | Self::Ice
+            | Self::Plant(..) => None,
+            Self::Sand(velocity, ..)
+            | Self::Water(velocity)
+            | Self::Acid(velocity, _)
+            | Self::Lava(velocity)
+            | Self::Oil(velocity) => Some(velocity.unpack()),
+        }
+    }
 
-impl Default for World {
-    fn default() -> Self {
-        let mut cells = [[CellElement::Air; WORLD_SIZE as usize]; WORLD_SIZE as usize];
+    pub fn density(&self) -> f32 {
+        self.kind().density()
+    }
 
-        for row in cells.iter_mut().take(30) {
-            for cell in row.iter_mut() {
-                *cell = CellElement::Sand(Vector2::zeros());
-            }
-        }
+    pub fn is_solid(&self) -> bool {
+        self.kind().is_solid()
+    }
 
-        Self { cells }
+    pub fn is_flammable(&self) -> bool {
+        self.kind().is_flammable()
     }
-}
 
-impl World {
-    /// Returns pixels in sRGB
-    pub fn pixels(&self) -> Vec<u8> {
-        let mut pixels = Vec::<u8>::new();
+    pub fn is_soluble(&self) -> bool {
+        self.kind().is_soluble()
+    }
 
-        for row in self.cells.iter() {
-            for cell in row.iter() {
-                cell.push_color(&mut pixels);
-            }
-        }
+    pub fn is_liquid(&self) -> bool {
+        self.kind().is_liquid()
+    }
 
-        pixels
+    /// Whether the parallel pass moves it. The other cells either never move or change the
+    /// cells around them, which only works one cell after the other
+    fn settles(&self) -> bool {
+        matches!(self, Self::Sand(..) | Self::Water(..) | Self::Oil(..))
     }
 
-    pub fn update(&mut self, forces: &Vec<Force>) {
-        for y in 0..WORLD_SIZE {
-            for x in 0..WORLD_SIZE {
-                self.update_cell(
-                    Coordinate::new(x, y),
-                    self.cells[y as usize][x as usize],
-                    forces,
-                );
-            }
+    /// Same cell moving at `velocity`, cells without one are returned as is
+    fn with_velocity(self, velocity: Vector2<f32>) -> Self {
+        let velocity = PackedVelocity::pack(velocity);
+        match self {
+            Self::Air
+            | Self::Stone
+            | Self::Fire(..)
+            | Self::Wood
+            | Self::Smoke(..)
+            | Self::Steam
+            | Self::Ice
+            | Self::Plant(..) => self,
+            Self::Sand(_, color, shade) => Self::Sand(velocity, color, shade),
+            Self::Water(_) => Self::Water(velocity),
+            Self::Acid(_, potency) => Self::Acid(velocity, potency),
+            Self::Lava(_) => Self::Lava(velocity),
+            Self::Oil(_) => Self::Oil(velocity),
         }
     }
 
-    fn update_cell(&mut self, mut coordinate: Coordinate, cell: CellElement, forces: &Vec<Force>) {
-        if let CellElement::Sand(mut velocity) = cell {
-            if velocity.magnitude_squared() > 1000.0 {
-                println!("WARN:coordinate{coordinate}velocity{velocity}");
+    fn push_color(&self, temperature: f32, palette: &Palette, pixels: &mut Vec<u8>) {
+        match self {
+            Self::Sand(_, color, shade) => {
+                let [r, g, b, a] = color.unwrap_or_else(|| palette.color(ElementKind::Sand));
+                pixels.extend_from_slice(&[
+                    r.saturating_sub(*shade),
+                    g.saturating_sub(*shade),
+                    b.saturating_sub(*shade),
+                    a,
+                ]);
             }
-            // forces
-            velocity += GRAVITY;
-
-            {
-                let position = coordinate.position();
-                for force in forces.iter() {
-                    let difference = force.position - position;
-                    let distance_squared = difference.magnitude_squared();
-                    if distance_squared >= force.min_distance_squared
-                        && distance_squared <= force.max_distance_squared
-                    {
-                        velocity += difference.normalize() * (force.strength / distance_squared);
-                    }
-                }
+            Self::Fire(lifetime) => {
+                // fades as it burns out
+                let brightness = 0.4 + 0.6 * *lifetime as f32 / FIRE_LIFETIME as f32;
+                let [r, g, b, a] = palette.color(ElementKind::Fire);
+                pixels.extend_from_slice(&[
+                    (r as f32 * brightness) as u8,
+                    (g as f32 * brightness) as u8,
+                    (b as f32 * brightness) as u8,
+                    a,
+                ]);
             }
-            // friction
-            {
-                if velocity.magnitude_squared() > AIR_FRICTION * AIR_FRICTION {
-                    velocity -= velocity.normalize() * AIR_FRICTION;
-                }
+            Self::Lava(..) => {
+                // dims and reddens as it cools toward setting
+                let glow = ((temperature - LAVA_SOLIDIFYING_TEMPERATURE)
+                    / (LAVA_TEMPERATURE - LAVA_SOLIDIFYING_TEMPERATURE))
+                    .clamp(0.0, 1.0);
+                let [r, g, b, a] = palette.color(ElementKind::Lava);
+                pixels.extend_from_slice(&[
+                    (r as f32 * (0.6 + 0.4 * glow)) as u8,
+                    (g as f32 * glow) as u8,
+                    (b as f32 * glow) as u8,
+                    a,
+                ]);
             }
+            _ => pixels.extend_from_slice(&palette.color(self.kind())),
+        }
+    }
+}
 
-            let destination: Coordinate;
-            {
-                let mut x = (coordinate.x as f32 + velocity.x).floor();
-                let mut y = (coordinate.y as f32 + velocity.y).floor();
+/// Which element a cell is, ignoring its payload
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ElementKind {
+    Air,
+    Sand,
+    Water,
+    Stone,
+    Fire,
+    Wood,
+    Smoke,
+    Acid,
+    Steam,
+    Ice,
+    Plant,
+    Lava,
+    Oil,
+}
 
-                // prevent overflows
-                if x < 0.0 {
-                    x = 0.0;
-                    velocity.x = 0.0;
-                }
+impl ElementKind {
+    pub const ALL: [Self; 13] = [
+        Self::Air,
+        Self::Sand,
+        Self::Water,
+        Self::Stone,
+        Self::Fire,
+        Self::Wood,
+        Self::Smoke,
+        Self::Acid,
+        Self::Steam,
+        Self::Ice,
+        Self::Plant,
+        Self::Lava,
+        Self::Oil,
+    ];
 
-                if y < 0.0 {
-                    y = 0.0;
-                    velocity.y = 0.0;
-                }
+    /// Heavier cells sink through lighter ones
+    pub fn density(self) -> f32 {
+        match self {
+            Self::Air | Self::Fire | Self::Smoke | Self::Steam => 0.0,
+            Self::Wood | Self::Plant => 0.7,
+            Self::Oil => 0.8,
+            Self::Ice => 0.9,
+            Self::Water => 1.0,
+            Self::Acid => 1.1,
+            Self::Sand => 1.6,
+            Self::Lava => 2.2,
+            Self::Stone => 2.5,
+        }
+    }
 
-                destination = Coordinate::new(x as u32, y as u32);
-            }
+    /// Solid cells can't be displaced, whatever their density
+    pub fn is_solid(self) -> bool {
+        matches!(self, Self::Stone | Self::Wood | Self::Ice | Self::Plant)
+    }
 
-            self.set_cell(&coordinate, CellElement::Sand(velocity));
+    /// Whether an adjacent fire sets this cell alight
+    pub fn is_flammable(self) -> bool {
+        matches!(self, Self::Wood | Self::Plant | Self::Oil)
+    }
 
-            if destination == coordinate {
-                return;
-            }
+    /// Whether adjacent acid can dissolve this cell
+    pub fn is_soluble(self) -> bool {
+        matches!(self, Self::Sand | Self::Stone | Self::Wood | Self::Plant)
+    }
 
-            for step_coordinate in path(&coordinate, &destination).drain(..) {
-                // check if blocked
-                if let Some(CellElement::Sand(..)) = self.get_cell(&step_coordinate) {
-                    // change trajectory to a random empty neighbor
-                    let unit = step_coordinate.difference(&coordinate);
-                    if let Some(mut neighbors) = unit.unit_neighbors() {
-                        if rand::thread_rng().gen_bool(0.5) {
-                            neighbors.swap(0, 1);
-                        }
+    /// Liquids spread sideways once they can't fall any further
+    pub fn is_liquid(self) -> bool {
+        matches!(self, Self::Water | Self::Acid | Self::Lava | Self::Oil)
+    }
 
-                        for neighbor in neighbors.iter() {
-                            let neighbor_coordinate = Coordinate::new(
-                                (coordinate.x as i32 + neighbor.x) as u32,
-                                (coordinate.y as i32 + neighbor.y) as u32,
-                            );
+    /// How far along a row a liquid looks for a lower column to even out with; thicker
+    /// liquids don't reach as far, and anything else doesn't look at all
+    pub fn spread(self) -> u32 {
+        match self {
+            Self::Water | Self::Acid => 10,
+            Self::Oil => 6,
+            Self::Lava => 2,
+            _ => 0,
+        }
+    }
+}
 
-                            if !neighbor_coordinate.in_bounds()
-                                || self.get_cell(&neighbor_coordinate) != Some(CellElement::Air)
-                            {
-                                continue;
-                            }
+/// How many cells of each element a world has
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ElementCounts {
+    pub air: usize,
+    pub sand: usize,
+    pub water: usize,
+    pub stone: usize,
+    pub fire: usize,
+    pub wood: usize,
+    pub smoke: usize,
+    pub acid: usize,
+    pub steam: usize,
+    pub ice: usize,
+    pub plant: usize,
+    pub lava: usize,
+    pub oil: usize,
+}
 
-                            self.swap_cells(&coordinate, &neighbor_coordinate);
-                            return;
-                        }
-                    }
+impl ElementCounts {
+    pub fn get(&self, kind: ElementKind) -> usize {
+        match kind {
+            ElementKind::Air => self.air,
+            ElementKind::Sand => self.sand,
+            ElementKind::Water => self.water,
+            ElementKind::Stone => self.stone,
+            ElementKind::Fire => self.fire,
+            ElementKind::Wood => self.wood,
+            ElementKind::Smoke => self.smoke,
+            ElementKind::Acid => self.acid,
+            ElementKind::Steam => self.steam,
+            ElementKind::Ice => self.ice,
+            ElementKind::Plant => self.plant,
+            ElementKind::Lava => self.lava,
+            ElementKind::Oil => self.oil,
+        }
+    }
 
-                    self.set_cell(&coordinate, CellElement::Sand(Vector2::zeros()));
-                    break;
-                }
+    fn add(&mut self, kind: ElementKind) {
+        let count = match kind {
+            ElementKind::Air => &mut self.air,
+            ElementKind::Sand => &mut self.sand,
+            ElementKind::Water => &mut self.water,
+            ElementKind::Stone => &mut self.stone,
+            ElementKind::Fire => &mut self.fire,
+            ElementKind::Wood => &mut self.wood,
+            ElementKind::Smoke => &mut self.smoke,
+            ElementKind::Acid => &mut self.acid,
+            ElementKind::Steam => &mut self.steam,
+            ElementKind::Ice => &mut self.ice,
+            ElementKind::Plant => &mut self.plant,
+            ElementKind::Lava => &mut self.lava,
+            ElementKind::Oil => &mut self.oil,
+        };
+        *count += 1;
+    }
+}
 
-                self.swap_cells(&coordinate, &step_coordinate);
-                coordinate = step_coordinate;
-            }
-        }
+/// What happens to cells that move past the edges of the world
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BoundaryMode {
+    /// the edges are walls that stop cells dead
+    #[default]
+    Solid,
+    /// cells leaving through one edge come back in through the opposite one
+    Wrap,
+    /// cells leaving the world are gone
+    Void,
+}
+
+/// Inclusive bounds of the cells that still need stepping
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct DirtyRect {
+    min: Coordinate,
+    max: Coordinate,
+}
+
+impl DirtyRect {
+    fn contains(&self, coordinate: &Coordinate) -> bool {
+        (self.min.x..=self.max.x).contains(&coordinate.x)
+            && (self.min.y..=self.max.y).contains(&coordinate.y)
     }
+}
 
-    pub fn swap_cells(&mut self, a_coordinate: &Coordinate, b_coordinate: &Coordinate) {
-        if let Some(a) = self.get_cell(a_coordinate) {
-            if let Some(b) = self.get_cell(b_coordinate) {
-                self.set_cell(a_coordinate, b);
-                self.set_cell(b_coordinate, a);
-            }
+/// Smallest bounds covering both, either of which may be empty
+fn merge_bounds(
+    bounds: Option<(Vector2<i64>, Vector2<i64>)>,
+    other: Option<(Vector2<i64>, Vector2<i64>)>,
+) -> Option<(Vector2<i64>, Vector2<i64>)> {
+    match (bounds, other) {
+        (Some((min, max)), Some((other_min, other_max))) => {
+            Some((min.inf(&other_min), max.sup(&other_max)))
         }
+        (bounds, None) | (None, bounds) => bounds,
     }
+}
 
-    pub fn get_cell(&self, coordinate: &Coordinate) -> Option<CellElement> {
-        if let Some(row) = self.cells.get(coordinate.y as usize) {
-            return row.get(coordinate.x as usize).cloned();
+/// How much the temperatures moved in a diffusion step
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct HeatChange {
+    /// largest change of any one cell
+    largest: f32,
+    /// inclusive bounds of the cells that changed by at least `HEAT_SETTLED`
+    changed: Option<(Vector2<i64>, Vector2<i64>)>,
+}
+
+impl HeatChange {
+    /// Both changes together; the order they're merged in makes no difference
+    fn merge(self, other: Self) -> Self {
+        Self {
+            largest: self.largest.max(other.largest),
+            changed: merge_bounds(self.changed, other.changed),
         }
-        None
     }
+}
 
-    pub fn set_cell(&mut self, coordinate: &Coordinate, cell: CellElement) {
-        self.cells[coordinate.y as usize][coordinate.x as usize] = cell;
+/// Where a cell wants to go in the parallel pass, and what it becomes once it's there
+#[derive(Clone, Copy)]
+struct Move {
+    /// index of the cell it swaps with
+    target: usize,
+    cell: CellElement,
+}
+
+/// What the parallel pass did to some of the cells
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct SettleChange {
+    mutations: u64,
+    /// inclusive bounds of the cells that changed
+    changed: Option<(Vector2<i64>, Vector2<i64>)>,
+    /// inclusive bounds of the cells that lost the cell they wanted to move into, and have
+    /// to try again next tick
+    restless: Option<(Vector2<i64>, Vector2<i64>)>,
+    /// whether cells of different temperatures swapped places
+    heat_moved: bool,
+}
+
+impl SettleChange {
+    /// Both changes together; the order they're merged in makes no difference
+    fn merge(self, other: Self) -> Self {
+        Self {
+            mutations: self.mutations + other.mutations,
+            changed: merge_bounds(self.changed, other.changed),
+            restless: merge_bounds(self.restless, other.restless),
+            heat_moved: self.heat_moved || other.heat_moved,
+        }
     }
 }
 
-pub struct Force {
-    pub position: Vector2<f32>,
-    pub strength: f32,
-    pub min_distance_squared: f32,
-    pub max_distance_squared: f32,
+/// Last move of a grain, keyed by the cell it moved into
+#[derive(Clone, Copy)]
+struct Bounce {
+    from: Coordinate,
+    /// consecutive moves back and forth between the same two cells
+    count: u8,
+    /// ticks since the move
+    age: u8,
 }
 
-/// translated from https://gist.github.com/DavidMcLaughlin208/60e69e698e3858617c322d80a8f174e2
-/// TODO optimize
-fn path(start: &Vector2<u32>, end: &Vector2<u32>) -> Vec<Vector2<u32>> {
-    if start == end {
-        return Vec::new();
+pub struct World {
+    width: u32,
+    height: u32,
+    /// row by row, starting at the bottom
+    cells: Vec<CellElement>,
+    /// in degrees Celsius, laid out like `cells`
+    temperature: Vec<f32>,
+    /// where `diffuse_heat` writes the next tick's temperatures, so it doesn't allocate
+    next_temperature: Vec<f32>,
+    /// temperatures are even enough that diffusing them would change nothing
+    heat_settled: bool,
+    bounces: HashMap<Coordinate, Bounce>,
+    /// cells whose contents already moved this tick, laid out like `cells`
+    moved: Vec<bool>,
+    /// cells to step next tick, `None` once everything is at rest
+    dirty: Option<DirtyRect>,
+    /// first and last row whose pixels changed since `clear_changed` was last called
+    changed_rows: Option<(u32, u32)>,
+    /// number of times a cell changed
+    mutations: u64,
+    /// sand grains acid dissolved or the void swallowed this tick, the only sand `update` may
+    /// remove
+    removed_sand: usize,
+    gravity_wells: Vec<GravityWell>,
+    gravity: Vector2<f32>,
+    /// speed a moving cell loses to the air every tick
+    air_friction: f32,
+    /// velocity of the air, which drags along every moving cell wherever it is
+    wind: Vector2<f32>,
+    /// cells a grain of sand has to be able to drop to slide off the grain beneath it
+    repose_steepness: u32,
+    boundary: BoundaryMode,
+    emitters: Vec<Emitter>,
+    palette: Palette,
+    /// number of updates run so far
+    tick: u64,
+    /// drives every random choice so a seeded world always plays out the same
+    rng: StdRng,
+    /// whether `update` moves cells in a double-buffered pass spread over threads
+    parallel: bool,
+    /// where the parallel pass writes the next tick's cells, laid out like `cells`
+    next_cells: Vec<CellElement>,
+    /// the move each cell picked in the parallel pass, laid out like `cells`
+    moves: Vec<Option<Move>>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::with_sand_bed(StdRng::from_entropy())
     }
+}
 
-    let mut path = Vec::<Vector2<u32>>::new();
+impl World {
+    /// Creates a world filled with air
+    pub fn new(width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+        let mut world = Self {
+            width,
+            height,
+            cells: vec![CellElement::Air; size],
+            temperature: vec![AMBIENT_TEMPERATURE; size],
+            next_temperature: vec![AMBIENT_TEMPERATURE; size],
+            heat_settled: true,
+            bounces: HashMap::new(),
+            moved: vec![false; size],
+            dirty: None,
+            changed_rows: Some((0, height.saturating_sub(1))),
+            mutations: 0,
+            removed_sand: 0,
+            gravity_wells: Vec::new(),
+            gravity: GRAVITY,
+            air_friction: AIR_FRICTION,
+            wind: Vector2::zeros(),
+            repose_steepness: 1,
+            boundary: BoundaryMode::default(),
+            emitters: Vec::new(),
+            palette: Palette::default(),
+            tick: 0,
+            rng: StdRng::from_entropy(),
+            parallel: false,
+            // both only allocated once the parallel pass first runs
+            next_cells: Vec::new(),
+            moves: Vec::new(),
+        };
+        world.mark_all_dirty();
+        world
+    }
 
-    let matrix_x1 = start.x as i32;
-    let matrix_y1 = start.y as i32;
-    let matrix_x2 = end.x as i32;
-    let matrix_y2 = end.y as i32;
+    /// Same as the default world, but every update is reproducible for a given `seed`
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_sand_bed(StdRng::seed_from_u64(seed))
+    }
 
-    let x_diff = matrix_x1 - matrix_x2;
-    let y_diff = matrix_y1 - matrix_y2;
+    /// The default world, with its sand shaded by `rng`
+    fn with_sand_bed(rng: StdRng) -> Self {
+        let mut world = Self {
+            rng,
+            ..Self::new(WORLD_SIZE, WORLD_SIZE)
+        };
 
-    let x_diff_is_larger = x_diff.abs() > y_diff.abs();
+        for index in 0..30 * WORLD_SIZE as usize {
+            world.cells[index] =
+                CellElement::Sand(PackedVelocity::ZERO, None, sand_shade(&mut world.rng));
+        }
 
-    let x_modifier: i32 = if x_diff < 0 { 1 } else { -1 };
-    let y_modifier: i32 = if y_diff < 0 { 1 } else { -1 };
+        world
+    }
 
-    let longer_side_length = x_diff.abs().max(y_diff.abs());
-    let shorter_side_length = x_diff.abs().min(y_diff.abs());
+    pub fn width(&self) -> u32 {
+        self.width
+    }
 
-    let slope = if shorter_side_length == 0 || longer_side_length == 0 {
-        0.0
-    } else {
-        shorter_side_length as f32 / longer_side_length as f32
-    };
+    pub fn height(&self) -> u32 {
+        self.height
+    }
 
-    let mut shorter_side_increase: i32;
+    pub fn in_bounds(&self, coordinate: &Coordinate) -> bool {
+        coordinate.x < self.width && coordinate.y < self.height
+    }
 
-    for i in 1..=longer_side_length {
-        shorter_side_increase = (slope * i as f32).round() as i32;
-        let x_increase: i32;
-        let y_increase: i32;
+    /// Position of a cell relative to the center of the world, which is where forces live
+    pub fn position(&self, coordinate: &Coordinate) -> Vector2<f32> {
+        Vector2::new(
+            coordinate.x as f32 - (self.width / 2) as f32,
+            coordinate.y as f32 - (self.height / 2) as f32,
+        )
+    }
 
-        if x_diff_is_larger {
-            x_increase = i;
-            y_increase = shorter_side_increase;
-        } else {
-            y_increase = i;
-            x_increase = shorter_side_increase;
+    /// The cell nearest to a centered `position`, the inverse of `World::position`
+    pub fn coordinate(&self, position: &Vector2<f32>) -> Option<Coordinate> {
+        let x = (position.x + (self.width / 2) as f32).round();
+        let y = (position.y + (self.height / 2) as f32).round();
+        if x < 0.0 || y < 0.0 {
+            return None;
         }
 
-        let current_y = matrix_y1 + (y_increase * y_modifier);
-        let current_x = matrix_x1 + (x_increase * x_modifier);
+        let coordinate = Coordinate::new(x as u32, y as u32);
+        self.in_bounds(&coordinate).then_some(coordinate)
+    }
 
-        //println!("x: {current_x}; y: {current_y}");
-        path.push(Vector2::new(current_x as u32, current_y as u32));
+    fn index(&self, coordinate: &Coordinate) -> usize {
+        (coordinate.y * self.width + coordinate.x) as usize
     }
 
-    path
-}
+    /// Every cell with its coordinate, row by row from the bottom
+    pub fn iter_cells(&self) -> impl Iterator<Item = (Coordinate, CellElement)> + '_ {
+        let width = self.width;
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(index, cell)| (index_coordinate(index, width), *cell))
+    }
 
-#[cfg(test)]
-mod tests {
-    use nalgebra::Vector2;
+    /// Like `iter_cells`, but the cells can be changed in place. Any of them might be, so the
+    /// whole world is stepped and redrawn afterwards
+    pub fn iter_cells_mut(&mut self) -> impl Iterator<Item = (Coordinate, &mut CellElement)> {
+        self.mark_all_dirty();
+        self.mark_all_changed();
+        let width = self.width;
+        self.cells
+            .iter_mut()
+            .enumerate()
+            .map(move |(index, cell)| (index_coordinate(index, width), cell))
+    }
 
-    use super::path;
+    /// Cells moving faster than `min_speed` cells per update, along with their velocities
+    pub fn fast_cells(&self, min_speed: f32) -> Vec<(Coordinate, Vector2<f32>)> {
+        self.iter_cells()
+            .filter_map(|(coordinate, cell)| Some((coordinate, cell.velocity()?)))
+            .filter(|(_, velocity)| velocity.norm() > min_speed)
+            .collect()
+    }
 
-    fn test_path(from: Vector2<u32>, to: Vector2<u32>, between: Vec<Vector2<u32>>) {
-        let path = path(&from, &to);
-        if from == to {
-            assert_eq!(path.len(), 0);
-            return;
+    /// Returns pixels in sRGB
+    pub fn pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::<u8>::new();
+        self.write_pixels(&mut pixels);
+        pixels
+    }
+
+    /// Replaces the contents of `pixels` with the world's pixels in sRGB, reusing its
+    /// allocation
+    pub fn write_pixels(&self, pixels: &mut Vec<u8>) {
+        pixels.clear();
+        pixels.reserve(self.cells.len() * 4);
+
+        for (cell, temperature) in self.cells.iter().zip(self.temperature.iter()) {
+            cell.push_color(*temperature, &self.palette, pixels);
         }
-        assert_eq!(path.len(), between.len() + 1);
-        for i in 0..between.len() {
-            assert_eq!(path[i], between[i]);
+    }
+
+    /// Returns pixels in sRGB with every cell upscaled to a `scale`x`scale` block,
+    /// along with the width and height of the resulting image
+    pub fn pixels_scaled(&self, scale: u32) -> (Vec<u8>, u32, u32) {
+        let width = self.width * scale;
+        let height = self.height * scale;
+        let mut pixels = Vec::<u8>::with_capacity((width * height * 4) as usize);
+
+        for row in self.pixels().chunks_exact(self.width as usize * 4) {
+            let mut scaled_row = Vec::<u8>::with_capacity(width as usize * 4);
+            for pixel in row.chunks_exact(4) {
+                for _ in 0..scale {
+                    scaled_row.extend_from_slice(pixel);
+                }
+            }
+
+            for _ in 0..scale {
+                pixels.extend_from_slice(&scaled_row);
+            }
         }
-        assert_eq!(path[between.len()], to);
+
+        (pixels, width, height)
     }
 
-    #[test]
-    fn path_works() {
-        test_path(Vector2::new(0, 0), Vector2::new(0, 0), vec![]);
-        test_path(Vector2::new(0, 0), Vector2::new(1, 0), vec![]);
-        test_path(Vector2::new(0, 0), Vector2::new(0, 1), vec![]);
-        test_path(Vector2::new(0, 0), Vector2::new(1, 1), vec![]);
-        test_path(Vector2::new(1, 0), Vector2::new(0, 0), vec![]);
-        test_path(Vector2::new(1, 1), Vector2::new(0, 0), vec![]);
-        test_path(Vector2::new(0, 1), Vector2::new(0, 0), vec![]);
+    /// The world's pixels as an image with one pixel per cell, top row first like any other
+    /// image so a saved snapshot is the right way up
+    pub fn to_rgba_image(&self) -> image::RgbaImage {
+        let row_length = self.width as usize * 4;
+        let pixels: Vec<u8> = self
+            .pixels()
+            .chunks_exact(row_length)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .expect("a world has four bytes of pixels per cell")
+    }
 
-        test_path(
-            Vector2::new(0, 0),
-            Vector2::new(0, 3),
-            vec![Vector2::new(0, 1), Vector2::new(0, 2)],
-        );
+    pub fn element_color(&self, which: ElementKind) -> [u8; 4] {
+        self.palette.color(which)
+    }
 
-        test_path(
-            Vector2::new(0, 0),
-            Vector2::new(3, 3),
-            vec![Vector2::new(1, 1), Vector2::new(2, 2)],
-        );
+    /// Recolors an element; takes effect the next time `pixels` is called
+    pub fn set_element_color(&mut self, which: ElementKind, color: [u8; 4]) {
+        self.palette.set_color(which, color);
+        self.mark_all_changed();
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// Swaps every color at once, e.g. to change themes
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.mark_all_changed();
+    }
+
+    /// First and last row, both inclusive, whose pixels changed since `clear_changed` was
+    /// last called, so a renderer only has to upload those
+    pub fn changed_rows(&self) -> Option<(u32, u32)> {
+        self.changed_rows
+    }
+
+    /// Call once the changed rows have been drawn
+    pub fn clear_changed(&mut self) {
+        self.changed_rows = None;
+    }
+
+    fn mark_all_changed(&mut self) {
+        self.changed_rows = Some((0, self.height.saturating_sub(1)));
+    }
+
+    /// Adds a well that pulls all sand toward `center` until the wells are cleared
+    pub fn add_gravity_well(&mut self, center: Vector2<f32>, strength: f32) {
+        self.gravity_wells.push(GravityWell { center, strength });
+    }
+
+    pub fn clear_gravity_wells(&mut self) {
+        self.gravity_wells.clear();
+    }
+
+    pub fn gravity(&self) -> Vector2<f32> {
+        self.gravity
+    }
+
+    /// Sets gravity in cells per tick squared; it can point any way, and settling, spreading
+    /// and rising all follow it
+    pub fn set_gravity(&mut self, gravity: Vector2<f32>) {
+        self.gravity = gravity;
+        // grains resting under the old gravity might not be anymore
+        self.mark_all_dirty();
+    }
+
+    pub fn air_friction(&self) -> f32 {
+        self.air_friction
+    }
+
+    pub fn set_air_friction(&mut self, air_friction: f32) {
+        self.air_friction = air_friction;
+        self.mark_all_dirty();
+    }
+
+    pub fn repose_steepness(&self) -> u32 {
+        self.repose_steepness
+    }
+
+    /// Sets how many cells a grain of sand has to be able to drop before it slides off the
+    /// grain beneath it. One lets it slide down any free diagonal, which piles sand at 45
+    /// degrees; every cell more makes piles that much steeper
+    pub fn set_repose_steepness(&mut self, repose_steepness: u32) {
+        self.repose_steepness = repose_steepness.max(1);
+        self.mark_all_dirty();
+    }
+
+    pub fn boundary(&self) -> BoundaryMode {
+        self.boundary
+    }
+
+    /// Sets what happens to cells that move past the edges; they're solid walls by default
+    pub fn set_boundary(&mut self, boundary: BoundaryMode) {
+        self.boundary = boundary;
+        // cells resting against the edges may be free to leave now
+        self.mark_all_dirty();
+    }
+
+    pub fn wind(&self) -> Vector2<f32> {
+        self.wind
+    }
+
+    /// Sets how hard the wind nudges every moving cell along, in cells per tick squared. Along
+    /// the wind, air friction takes that fraction of a cell's drift off every tick, so cells
+    /// level off at wind / air friction rather than speeding up forever. The air is still by
+    /// default
+    pub fn set_wind(&mut self, wind: Vector2<f32>) {
+        self.wind = wind;
+    }
+
+    pub fn parallel(&self) -> bool {
+        self.parallel
+    }
+
+    /// Has `update` move cells with a pass that works out each row of the next tick from the
+    /// last one by itself, spread over rayon's threads, instead of stepping the cells one
+    /// after the other.
+    /// It plays out the same however many threads it gets, but not the same as the serial
+    /// steps: cells move a cell a tick at most, liquids spread a cell at a time without
+    /// leveling out, and the edges are solid whatever the boundary mode. Fire, smoke, steam,
+    /// acid, lava and plants still take their turns one after the other once it's done.
+    /// It's off by default
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    /// Makes every random choice from here on the same for a given `seed`
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn add_emitter(&mut self, emitter: Emitter) {
+        self.emitters.push(emitter);
+    }
+
+    /// Spawns a cell from every emitter whose cell is free
+    fn emit(&mut self) {
+        for index in 0..self.emitters.len() {
+            let emitter = &mut self.emitters[index];
+            emitter.accumulated += emitter.rate;
+            if emitter.accumulated < 1.0 {
+                continue;
+            }
+            // a single cell can't take more than one grain per tick
+            emitter.accumulated %= 1.0;
+
+            let emitter = *emitter;
+            if self.get_cell(&emitter.coordinate) == Some(CellElement::Air) {
+                let velocity = emitter.spawn_velocity(&mut self.rng);
+                let cell = match CellElement::new(emitter.element, &mut self.rng) {
+                    CellElement::Sand(_, _, shade) => {
+                        CellElement::Sand(velocity.into(), emitter.color, shade)
+                    }
+                    cell => cell.with_velocity(velocity),
+                };
+                self.set_cell(&emitter.coordinate, cell);
+            }
+        }
+    }
+
+    pub fn update(&mut self, forces: &[Force]) {
+        self.emit();
+        #[cfg(debug_assertions)]
+        let sand_before = self.counts().sand;
+        #[cfg(debug_assertions)]
+        let stepped = self.dirty;
+        self.removed_sand = 0;
+
+        // forces and wells can set resting cells in motion, so the cells in their reach
+        // have to be stepped even if nothing changed there
+        for force in forces.iter() {
+            let (min, max) = self.reach(force);
+            self.mark_dirty(min, max);
+        }
+        let forces = ForceGrid::new(self, forces);
+        if !self.gravity_wells.is_empty() || self.wind != Vector2::zeros() {
+            self.mark_all_dirty();
+        }
+
+        self.bounces.retain(|_, bounce| {
+            bounce.age += 1;
+            bounce.age <= OSCILLATION_WINDOW
+        });
+
+        self.moved.fill(false);
+
+        // anything that changes while stepping marks itself dirty again for the next tick
+        if let Some(dirty) = self.dirty.take() {
+            if self.parallel {
+                self.settle(dirty, &forces);
+                self.sweep(dirty, &forces, |cell| !cell.settles());
+            } else {
+                self.sweep(dirty, &forces, |_| true);
+            }
+        }
+
+        self.diffuse_heat();
+
+        #[cfg(debug_assertions)]
+        self.check_sand_conserved(sand_before, stepped);
+
+        self.tick += 1;
+    }
+
+    /// Steps the cells in `dirty` that `steps` picks, one after the other
+    fn sweep(
+        &mut self,
+        DirtyRect { min, max }: DirtyRect,
+        forces: &ForceGrid,
+        steps: impl Fn(&CellElement) -> bool,
+    ) {
+        // sweeping every row the same way lets the cells scanned first claim the gaps both
+        // sides want, so the sweep flips direction each tick to cancel out the drift
+        let reverse = self.tick % 2 == 1;
+        for y in min.y..=max.y {
+            for i in min.x..=max.x {
+                let x = if reverse { max.x - (i - min.x) } else { i };
+                let coordinate = Coordinate::new(x, y);
+                let index = self.index(&coordinate);
+                // a cell that moved into a spot the scan hasn't reached yet already had its turn
+                if self.moved[index] || !steps(&self.cells[index]) {
+                    continue;
+                }
+
+                let destination = self.update_cell(coordinate, self.cells[index], forces);
+
+                if destination != coordinate {
+                    self.track_bounce(coordinate, destination);
+                }
+            }
+        }
+    }
+
+    /// Moves the settling cells in `stepped` a cell at most, working out the next tick from
+    /// nothing but this one. First every cell picks where it wants to go, then every cell
+    /// works out what ends up in it from those picks, both times a row at a time in parallel
+    fn settle(&mut self, stepped: DirtyRect, forces: &ForceGrid) {
+        let width = self.width as usize;
+        let size = self.cells.len();
+        // the random choices are drawn from the cells' indices, so no thread waits on another
+        let salt = self.rng.gen::<u64>();
+
+        let mut moves = std::mem::take(&mut self.moves);
+        moves.resize(size, None);
+        moves
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, choice) in row.iter_mut().enumerate() {
+                    let coordinate = Coordinate::new(x as u32, y as u32);
+                    *choice = self.choose_move(&coordinate, &stepped, forces, salt);
+                }
+            });
+
+        let mut next_cells = std::mem::take(&mut self.next_cells);
+        next_cells.resize(size, CellElement::Air);
+        let mut next_temperature = std::mem::take(&mut self.next_temperature);
+        let change = next_cells
+            .par_chunks_mut(width)
+            .zip(next_temperature.par_chunks_mut(width))
+            .enumerate()
+            .map(|(y, (cells, temperature))| {
+                self.settle_row(y as u32, &stepped, &moves, salt, cells, temperature)
+            })
+            .reduce(SettleChange::default, SettleChange::merge);
+
+        self.moves = moves;
+        self.next_cells = std::mem::replace(&mut self.cells, next_cells);
+        self.next_temperature = std::mem::replace(&mut self.temperature, next_temperature);
+
+        self.mutations += change.mutations;
+        if let Some((min, max)) = change.changed {
+            self.mark_changed(min, max);
+        }
+        if let Some((min, max)) = change.restless {
+            self.mark_dirty(min, max);
+        }
+        if change.heat_moved {
+            self.heat_settled = false;
+        }
+    }
+
+    /// Velocity a settling cell in `stepped` moves on with and the way it's headed, or `None`
+    /// if it's still or about to boil or freeze
+    fn heading(
+        &self,
+        coordinate: &Coordinate,
+        stepped: &DirtyRect,
+        forces: &ForceGrid,
+    ) -> Option<(Vector2<f32>, Vector2<i32>)> {
+        let index = self.index(coordinate);
+        let cell = self.cells[index];
+        if !stepped.contains(coordinate) || !cell.settles() || self.changes_phase(index) {
+            return None;
+        }
+
+        let velocity = self.accelerate(coordinate, cell.velocity()?, forces);
+        let direction = velocity.try_normalize(f32::EPSILON)?;
+        Some((velocity, direction.map(|value| value.round() as i32)))
+    }
+
+    /// Where the cell at `coordinate` wants to go this tick: the way it's headed, else down
+    /// either side of it, else for liquids sideways
+    fn choose_move(
+        &self,
+        coordinate: &Coordinate,
+        stepped: &DirtyRect,
+        forces: &ForceGrid,
+        salt: u64,
+    ) -> Option<Move> {
+        let (velocity, heading) = self.heading(coordinate, stepped, forces)?;
+        let cell = self.cells[self.index(coordinate)];
+        let to = |target: Coordinate, velocity| Move {
+            target: self.index(&target),
+            cell: cell.with_velocity(velocity),
+        };
+
+        if let Some(ahead) = self.offset(coordinate, &heading) {
+            if self.can_displace(&cell, &ahead) {
+                return Some(to(ahead, velocity));
+            }
+            // a cell that's moving out of the way is followed rather than slid off of, or
+            // falling columns would fan out
+            let follows = self
+                .heading(&ahead, stepped, forces)
+                .and_then(|(_, next)| self.offset(&ahead, &next))
+                .is_some_and(|next| self.can_displace(&self.cells[self.index(&ahead)], &next));
+            if follows {
+                return None;
+            }
+        }
+
+        let random = mix(salt, self.index(coordinate));
+        let mut diagonals = heading.unit_neighbors()?;
+        if random & 1 == 1 {
+            diagonals.swap(0, 1);
+        }
+        for diagonal in diagonals {
+            if let Some(target) = self.offset(coordinate, &diagonal) {
+                if self.can_displace(&cell, &target) && self.steep_enough(&cell, &target) {
+                    return Some(to(target, velocity));
+                }
+            }
+        }
+
+        if cell.is_liquid() {
+            let across = perpendicular(&self.down());
+            let mut sides = [across, -across];
+            if random & 2 == 2 {
+                sides.swap(0, 1);
+            }
+            for side in sides {
+                if let Some(target) = self.offset(coordinate, &side) {
+                    if self.can_displace(&cell, &target) {
+                        return Some(to(target, Vector2::zeros()));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The cell whose move into `target` goes ahead, if any. A cell that's moving itself
+    /// can't be moved into, and out of the cells that want to, the one falling straight in
+    /// wins, then the ones coming in from above, then from the sides
+    fn winner(&self, target: usize, moves: &[Option<Move>], salt: u64) -> Option<usize> {
+        if moves[target].is_some() {
+            return None;
+        }
+
+        let up = -self.down();
+        let mut above = up.unit_neighbors().unwrap();
+        let across = perpendicular(&up);
+        let mut sides = [across, -across];
+        let mut below = (-up).unit_neighbors().unwrap();
+        let random = mix(salt, target);
+        if random & 1 == 1 {
+            above.swap(0, 1);
+            below.swap(0, 1);
+        }
+        if random & 2 == 2 {
+            sides.swap(0, 1);
+        }
+
+        let coordinate = index_coordinate(target, self.width);
+        [
+            up, above[0], above[1], sides[0], sides[1], below[0], below[1], -up,
+        ]
+        .iter()
+        .filter_map(|step| self.offset(&coordinate, step))
+        .map(|neighbor| self.index(&neighbor))
+        .find(|&neighbor| moves[neighbor].is_some_and(|choice| choice.target == target))
+    }
+
+    /// Works out row `y` of the next tick into `cells` and `temperature` from the moves the
+    /// cells picked
+    fn settle_row(
+        &self,
+        y: u32,
+        stepped: &DirtyRect,
+        moves: &[Option<Move>],
+        salt: u64,
+        cells: &mut [CellElement],
+        temperature: &mut [f32],
+    ) -> SettleChange {
+        let width = self.width as usize;
+        let start = y as usize * width;
+        // only the rows cells were stepped in and the ones they could move into change
+        if y + 1 < stepped.min.y || y > stepped.max.y + 1 {
+            cells.copy_from_slice(&self.cells[start..start + width]);
+            temperature.copy_from_slice(&self.temperature[start..start + width]);
+            return SettleChange::default();
+        }
+
+        let mut change = SettleChange::default();
+        for x in 0..width {
+            let index = start + x;
+            let coordinate = Vector2::new(x as i64, y as i64);
+            let (cell, from) = match moves[index] {
+                // it swaps with the cell it displaced
+                Some(choice) if self.winner(choice.target, moves, salt) == Some(index) => {
+                    (self.cells[choice.target], choice.target)
+                }
+                Some(_) => {
+                    change.restless = merge_bounds(change.restless, Some((coordinate, coordinate)));
+                    (self.cells[index], index)
+                }
+                None => match self.winner(index, moves, salt) {
+                    Some(mover) => (moves[mover].unwrap().cell, mover),
+                    None => (
+                        self.stay(&index_coordinate(index, self.width), stepped),
+                        index,
+                    ),
+                },
+            };
+
+            cells[x] = cell;
+            temperature[x] = self.temperature[from];
+            if cell != self.cells[index] {
+                change.mutations += 1;
+                change.changed = merge_bounds(change.changed, Some((coordinate, coordinate)));
+            }
+            change.heat_moved |= self.temperature[from] != self.temperature[index];
+        }
+        change
+    }
+
+    /// What the cell at `coordinate` turns into when nothing moves in or out of it
+    fn stay(&self, coordinate: &Coordinate, stepped: &DirtyRect) -> CellElement {
+        let index = self.index(coordinate);
+        let cell = self.cells[index];
+        if !stepped.contains(coordinate) || !cell.settles() {
+            return cell;
+        }
+
+        let temperature = self.temperature[index];
+        match cell {
+            CellElement::Water(..) if temperature > BOILING_TEMPERATURE => CellElement::Steam,
+            CellElement::Water(..) if temperature < FREEZING_TEMPERATURE => CellElement::Ice,
+            // it had nowhere to go, so it comes to rest
+            cell => cell.with_velocity(Vector2::zeros()),
+        }
+    }
+
+    /// Whether the water at `index` boils or freezes this tick
+    fn changes_phase(&self, index: usize) -> bool {
+        let temperature = self.temperature[index];
+        matches!(self.cells[index], CellElement::Water(..))
+            && !(FREEZING_TEMPERATURE..=BOILING_TEMPERATURE).contains(&temperature)
+    }
+
+    /// Panics if stepping the cells in `stepped` created sand or lost any that acid didn't
+    /// dissolve and didn't fall out of the world
+    #[cfg(debug_assertions)]
+    fn check_sand_conserved(&self, before: usize, stepped: Option<DirtyRect>) {
+        let after = self.counts().sand;
+        assert!(
+            after + self.removed_sand == before,
+            "tick {}: {before} sand grains became {after} with {} dissolved by acid or lost \
+             to the void, while stepping {stepped:?}",
+            self.tick,
+            self.removed_sand,
+        );
+    }
+
+    /// Blasts every moving cell within `radius` of `center` away from it all at once, with the
+    /// same falloff as a repelling `Force` of `strength`. Solids are left standing
+    pub fn explode(&mut self, center: &Coordinate, radius: u32, strength: f32) {
+        let blast = Force {
+            position: self.position(center),
+            strength,
+            kind: ForceKind::Repel,
+            min_distance_squared: 0.0,
+            max_distance_squared: (radius * radius) as f32,
+        };
+        let (min, max) = self.reach(&blast);
+        let min = min.sup(&Vector2::zeros()).map(|bound| bound as u32);
+        let max = max
+            .inf(&Vector2::new(self.width as i64 - 1, self.height as i64 - 1))
+            .map(|bound| bound as u32);
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let coordinate = Coordinate::new(x, y);
+                let cell = self.cells[self.index(&coordinate)];
+                let Some(velocity) = cell.velocity() else {
+                    continue;
+                };
+                let position = self.position(&coordinate);
+                let velocity = apply_forces(velocity, &position, std::iter::once(&blast))
+                    .cap_magnitude(MAX_VELOCITY);
+                self.set_cell(&coordinate, cell.with_velocity(velocity));
+            }
+        }
+    }
+
+    /// Corners of the box around every cell `force` can reach, which may lie outside the world
+    fn reach(&self, force: &Force) -> (Vector2<i64>, Vector2<i64>) {
+        let reach = Vector2::repeat(force.max_distance_squared.sqrt());
+        let center = force.position + Vector2::new(self.width / 2, self.height / 2).cast();
+        (
+            (center - reach).map(|bound| bound.floor() as i64),
+            (center + reach).map(|bound| bound.ceil() as i64),
+        )
+    }
+
+    /// Evens temperatures out between neighbors; heat only flows from cell to cell, so none
+    /// is lost through the edges of the world. Cells whose temperature changed are stepped
+    /// next tick, so resting ones still get to boil, condense, melt or freeze
+    fn diffuse_heat(&mut self) {
+        if self.heat_settled {
+            return;
+        }
+
+        // every row only reads last tick's temperatures, so they can all be worked out at once
+        let width = self.width as usize;
+        let temperature = &self.temperature;
+        let change = self
+            .next_temperature
+            .par_chunks_mut(width)
+            .enumerate()
+            .map(|(y, next)| diffuse_row(temperature, width, y, next))
+            .reduce(HeatChange::default, HeatChange::merge);
+
+        std::mem::swap(&mut self.temperature, &mut self.next_temperature);
+        self.heat_settled = change.largest < HEAT_SETTLED;
+        if let Some((min, max)) = change.changed {
+            self.mark_dirty(min, max);
+        }
+    }
+
+    /// How many times `update` has run
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// How many times a cell has changed since the world was created
+    pub fn mutations(&self) -> u64 {
+        self.mutations
+    }
+
+    /// Fingerprint of every cell, velocities and all, for telling whether two worlds are in
+    /// the same state without comparing them cell by cell. Temperatures and settings like
+    /// gravity aren't part of it
+    pub fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.width, self.height).hash(&mut hasher);
+        self.cells.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Counts the cells of every element in one pass over the world
+    pub fn counts(&self) -> ElementCounts {
+        let mut counts = ElementCounts::default();
+        for cell in &self.cells {
+            counts.add(cell.kind());
+        }
+        counts
+    }
+
+    /// Schedules every cell between `min` and `max` for the next update, both inclusive
+    /// and clamped to the world
+    fn mark_dirty(&mut self, min: Vector2<i64>, max: Vector2<i64>) {
+        let min = min.sup(&Vector2::zeros());
+        let max = max.inf(&Vector2::new(self.width as i64 - 1, self.height as i64 - 1));
+        if min.x > max.x || min.y > max.y {
+            return;
+        }
+
+        let (min, max) = (min.map(|bound| bound as u32), max.map(|bound| bound as u32));
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => DirtyRect {
+                min: dirty.min.inf(&min),
+                max: dirty.max.sup(&max),
+            },
+            None => DirtyRect { min, max },
+        });
+    }
+
+    fn mark_all_dirty(&mut self) {
+        self.mark_dirty(
+            Vector2::zeros(),
+            Vector2::new(self.width as i64, self.height as i64),
+        );
+    }
+
+    /// Puts a grain to rest if it keeps jittering between the same two cells
+    fn track_bounce(&mut self, from: Coordinate, to: Coordinate) {
+        let count = match self.bounces.remove(&from) {
+            Some(bounce) if bounce.from == to => bounce.count.saturating_add(1),
+            _ => 0,
+        };
+
+        if count < OSCILLATION_LIMIT {
+            self.bounces.insert(
+                to,
+                Bounce {
+                    from,
+                    count,
+                    age: 0,
+                },
+            );
+            return;
+        }
+
+        // undo the move; the cells in between are only restored if they were all air
+        self.swap_cells(&from, &to);
+        if let Some(cell) = self.get_cell(&from) {
+            self.set_cell(&from, cell.with_velocity(Vector2::zeros()));
+        }
+        self.bounces.insert(
+            from,
+            Bounce {
+                from: to,
+                count,
+                age: 0,
+            },
+        );
+    }
+
+    /// Returns the coordinate the cell ended up at
+    fn update_cell(
+        &mut self,
+        mut coordinate: Coordinate,
+        cell: CellElement,
+        forces: &ForceGrid,
+    ) -> Coordinate {
+        if let CellElement::Fire(lifetime) = cell {
+            return self.burn(coordinate, lifetime);
+        }
+
+        if let CellElement::Smoke(lifetime) = cell {
+            return self.drift(coordinate, lifetime);
+        }
+
+        if let CellElement::Plant(height) = cell {
+            self.grow(coordinate, height);
+            return coordinate;
+        }
+
+        let temperature = self.temperature[self.index(&coordinate)];
+        if cell == CellElement::Steam {
+            if temperature < CONDENSATION_TEMPERATURE {
+                self.set_cell(&coordinate, CellElement::Water(PackedVelocity::ZERO));
+                return coordinate;
+            }
+            return self.rise(coordinate);
+        }
+        if cell == CellElement::Ice {
+            if temperature > MELTING_TEMPERATURE {
+                self.set_cell(&coordinate, CellElement::Water(PackedVelocity::ZERO));
+            }
+            return coordinate;
+        }
+        if matches!(cell, CellElement::Water(..)) {
+            if temperature > BOILING_TEMPERATURE {
+                self.set_cell(&coordinate, CellElement::Steam);
+                return coordinate;
+            }
+            if temperature < FREEZING_TEMPERATURE {
+                self.set_cell(&coordinate, CellElement::Ice);
+                return coordinate;
+            }
+        }
+
+        let cell = match cell {
+            CellElement::Acid(velocity, potency) => match self.corrode(coordinate, potency) {
+                Some(potency) => CellElement::Acid(velocity, potency),
+                None => return coordinate,
+            },
+            CellElement::Lava(..) => {
+                if !self.glow(coordinate) {
+                    return coordinate;
+                }
+                if !self.rng.gen_bool(LAVA_FLOW_CHANCE) {
+                    // it hasn't come to rest, so it has to try again next tick
+                    let center = coordinate.cast::<i64>();
+                    self.mark_dirty(center, center);
+                    return coordinate;
+                }
+                cell
+            }
+            cell => cell,
+        };
+
+        if let Some(velocity) = cell.velocity() {
+            let mut velocity = self.accelerate(&coordinate, velocity, forces);
+            let (destination, overflow) = self.destination(&coordinate, &mut velocity);
+
+            // only written back once it's known where the cell ends up, so a cell at rest
+            // doesn't count as changed
+            let moving = cell.with_velocity(velocity);
+
+            if destination == coordinate {
+                self.set_cell(&coordinate, moving);
+                return self.cross_boundary(coordinate, overflow);
+            }
+
+            for step_coordinate in path(&coordinate, &destination).drain(..) {
+                // check if blocked
+                if !self.can_displace(&cell, &step_coordinate) {
+                    // change trajectory to a random neighbor it can sink into
+                    let unit = step_coordinate.difference(&coordinate);
+                    if let Some(mut neighbors) = unit.unit_neighbors() {
+                        if self.rng.gen_bool(0.5) {
+                            neighbors.swap(0, 1);
+                        }
+
+                        for neighbor in neighbors.iter() {
+                            let neighbor_coordinate = Coordinate::new(
+                                (coordinate.x as i32 + neighbor.x) as u32,
+                                (coordinate.y as i32 + neighbor.y) as u32,
+                            );
+
+                            if !self.in_bounds(&neighbor_coordinate)
+                                || !self.can_displace(&cell, &neighbor_coordinate)
+                                || self.contested(&coordinate, &neighbor_coordinate)
+                                || !self.steep_enough(&cell, &neighbor_coordinate)
+                            {
+                                continue;
+                            }
+
+                            self.set_cell(&coordinate, moving);
+                            self.swap_cells(&coordinate, &neighbor_coordinate);
+                            return neighbor_coordinate;
+                        }
+                    }
+
+                    self.set_cell(&coordinate, cell.with_velocity(Vector2::zeros()));
+                    if cell.is_liquid() {
+                        let spread = cell.kind().spread();
+                        if let Some(side) = self.update_fluid(&cell, coordinate, spread) {
+                            return side;
+                        }
+                    }
+                    return coordinate;
+                }
+
+                self.set_cell(&coordinate, moving);
+                self.swap_cells(&coordinate, &step_coordinate);
+                coordinate = step_coordinate;
+            }
+            // it made it all the way to the edge it was heading past
+            return self.cross_boundary(coordinate, overflow);
+        }
+
+        coordinate
+    }
+
+    /// What a cell at `coordinate` moving at `velocity` speeds up or slows down to over a tick
+    fn accelerate(
+        &self,
+        coordinate: &Coordinate,
+        mut velocity: Vector2<f32>,
+        forces: &ForceGrid,
+    ) -> Vector2<f32> {
+        let wind = (self.wind != Vector2::zeros()).then(|| self.wind.normalize());
+        // forces
+        if let Some(direction) = wind {
+            // dragged back by how fast it already drifts, before this tick's nudge
+            let drift = velocity.dot(&direction);
+            velocity += self.wind - direction * drift * self.air_friction;
+        }
+        velocity += self.gravity;
+
+        {
+            let position = self.position(coordinate);
+            velocity = apply_forces(velocity, &position, forces.near(coordinate));
+
+            for well in self.gravity_wells.iter() {
+                let difference = well.center - position;
+                let distance_squared = difference.magnitude_squared();
+                // a grain sitting on the center has no direction to be pulled in
+                if distance_squared > 0.0 {
+                    velocity += difference.normalize()
+                        * (well.strength / distance_squared.max(MIN_FALLOFF_DISTANCE_SQUARED));
+                }
+            }
+        }
+        // friction, across the wind since the drag above already covers the way it blows
+        {
+            let across = match wind {
+                Some(direction) => velocity - direction * velocity.dot(&direction),
+                None => velocity,
+            };
+            if across.magnitude_squared() > self.air_friction * self.air_friction {
+                velocity -= across.normalize() * self.air_friction;
+            }
+        }
+        // overlapping forces can add up to speeds that would fling a cell across the world
+        // in a single tick
+        velocity.cap_magnitude(MAX_VELOCITY)
+    }
+
+    /// Sends a cell that reached the edge it's moving past, `overflow` pointing out through
+    /// it, wherever the boundary mode has it go, returning where it ended up
+    fn cross_boundary(&mut self, coordinate: Coordinate, overflow: Vector2<i32>) -> Coordinate {
+        if overflow == Vector2::zeros() {
+            return coordinate;
+        }
+
+        match self.boundary {
+            BoundaryMode::Solid => coordinate,
+            BoundaryMode::Wrap => {
+                let wrap = |position: u32, overflow: i32, size: u32| match overflow {
+                    1 => 0,
+                    -1 => size - 1,
+                    _ => position,
+                };
+                let other_side = Coordinate::new(
+                    wrap(coordinate.x, overflow.x, self.width),
+                    wrap(coordinate.y, overflow.y, self.height),
+                );
+                let cell = self.cells[self.index(&coordinate)];
+                if other_side != coordinate && self.can_displace(&cell, &other_side) {
+                    self.swap_cells(&coordinate, &other_side);
+                    return other_side;
+                }
+                coordinate
+            }
+            BoundaryMode::Void => {
+                if let Some(CellElement::Sand(..)) = self.get_cell(&coordinate) {
+                    self.removed_sand += 1;
+                }
+                self.set_cell(&coordinate, CellElement::Air);
+                coordinate
+            }
+        }
+    }
+
+    /// Whether the cell above `to` could fall straight into it; straight falls win over
+    /// sideways moves so grains to the left don't get first pick of every gap
+    fn contested(&self, from: &Coordinate, to: &Coordinate) -> bool {
+        let Some(above) = self.offset(to, &-self.down()) else {
+            return false;
+        };
+        if above == *from {
+            return false;
+        }
+
+        match self.get_cell(&above) {
+            Some(cell) if cell.velocity().is_some() => self.can_displace(&cell, to),
+            _ => false,
+        }
+    }
+
+    /// Whether sliding `cell` into `coordinate` goes down a slope steep enough for it to slide
+    /// at all; sand needs the `repose_steepness` cells from there down to all be free, other
+    /// cells slide anywhere they can displace
+    fn steep_enough(&self, cell: &CellElement, coordinate: &Coordinate) -> bool {
+        if !matches!(cell, CellElement::Sand(..)) {
+            return true;
+        }
+
+        let down = self.down();
+        (1..self.repose_steepness as i32).all(|depth| {
+            self.offset(coordinate, &(down * depth))
+                .is_some_and(|below| self.can_displace(cell, &below))
+        })
+    }
+
+    /// Whether `cell` is heavier than whatever is at `coordinate`; the edges block everything
+    fn can_displace(&self, cell: &CellElement, coordinate: &Coordinate) -> bool {
+        matches!(
+            self.get_cell(coordinate),
+            Some(target) if !target.is_solid() && cell.density() > target.density()
+        )
+    }
+
+    /// Burns a fire down by a tick, lights whatever flammable cells surround it and lets it
+    /// drift upward, returning where it ended up
+    fn burn(&mut self, coordinate: Coordinate, lifetime: u8) -> Coordinate {
+        self.heat(&coordinate, FIRE_TEMPERATURE);
+
+        if lifetime <= 1 {
+            self.set_cell(&coordinate, CellElement::Smoke(SMOKE_LIFETIME));
+            return coordinate;
+        }
+
+        for (neighbor, cell) in self.neighbors(&coordinate).into_iter().flatten() {
+            if cell.is_flammable() {
+                self.set_cell(&neighbor, CellElement::Fire(FIRE_LIFETIME));
+                // a fresh fire waits a tick before it spreads any further
+                let index = self.index(&neighbor);
+                self.moved[index] = true;
+            }
+        }
+
+        self.set_cell(&coordinate, CellElement::Fire(lifetime - 1));
+
+        if self.rng.gen_bool(FIRE_RISE_CHANCE) {
+            let up = -self.down();
+            let diagonals = up.unit_neighbors().unwrap();
+            let step = [diagonals[1], up, diagonals[0]][(self.rng.gen_range(-1..=1) + 1) as usize];
+            if let Some(above) = self.offset(&coordinate, &step) {
+                if self.get_cell(&above) == Some(CellElement::Air) {
+                    self.swap_cells(&coordinate, &above);
+                    return above;
+                }
+            }
+        }
+
+        coordinate
+    }
+
+    /// Dissolves soluble cells around a drop of acid, returning how many more it can take or
+    /// `None` once it's used up and gone
+    fn corrode(&mut self, coordinate: Coordinate, mut potency: u8) -> Option<u8> {
+        let mut touching = false;
+        for (neighbor, cell) in self.neighbors(&coordinate).into_iter().flatten() {
+            if !cell.is_soluble() {
+                continue;
+            }
+
+            touching = true;
+            if self.rng.gen_bool(ACID_DISSOLVE_CHANCE) {
+                if let CellElement::Sand(..) = cell {
+                    self.removed_sand += 1;
+                }
+                self.set_cell(&neighbor, CellElement::Air);
+                potency -= 1;
+                if potency == 0 {
+                    self.set_cell(&coordinate, CellElement::Air);
+                    return None;
+                }
+            }
+        }
+
+        // acid resting on something it hasn't dissolved yet has to keep trying
+        if touching {
+            let center = Vector2::new(coordinate.x as i64, coordinate.y as i64);
+            self.mark_dirty(center, center);
+        }
+
+        Some(potency)
+    }
+
+    /// Keeps lava hot, lights whatever flammable cells surround it and boils the water it
+    /// touches, which draws heat out of it. Returns whether it's still molten, as lava
+    /// that cools below `LAVA_SOLIDIFYING_TEMPERATURE` sets into stone
+    fn glow(&mut self, coordinate: Coordinate) -> bool {
+        self.heat(&coordinate, LAVA_TEMPERATURE);
+
+        let index = self.index(&coordinate);
+        for (neighbor, cell) in self.neighbors(&coordinate).into_iter().flatten() {
+            match cell {
+                _ if cell.is_flammable() => {
+                    self.set_cell(&neighbor, CellElement::Fire(FIRE_LIFETIME));
+                    let index = self.index(&neighbor);
+                    self.moved[index] = true;
+                }
+                // once it's set, the rest of the water only gets its heat through diffusion
+                CellElement::Water(..)
+                    if self.temperature[index] >= LAVA_SOLIDIFYING_TEMPERATURE =>
+                {
+                    // the water takes the heat the lava loses, which is plenty to boil it
+                    let water = self.index(&neighbor);
+                    self.heat(&neighbor, self.temperature[water] + LAVA_QUENCHING);
+                    self.temperature[index] -= LAVA_QUENCHING;
+                }
+                _ => {}
+            }
+        }
+
+        if self.temperature[index] < LAVA_SOLIDIFYING_TEMPERATURE {
+            self.set_cell(&coordinate, CellElement::Stone);
+            return false;
+        }
+        true
+    }
+
+    /// Grows a plant into the air above it by drinking a drop of water it touches, until it
+    /// reaches `PLANT_MAX_HEIGHT`
+    fn grow(&mut self, coordinate: Coordinate, height: u8) {
+        let Some(above) = self.offset(&coordinate, &-self.down()) else {
+            return;
+        };
+        if height >= PLANT_MAX_HEIGHT || self.get_cell(&above) != Some(CellElement::Air) {
+            return;
+        }
+
+        let mut water = None;
+        for (neighbor, cell) in self.neighbors(&coordinate).into_iter().flatten() {
+            if let CellElement::Water(..) = cell {
+                water = Some(neighbor);
+            }
+        }
+        let Some(water) = water else {
+            return;
+        };
+
+        if self.rng.gen_bool(PLANT_GROWTH_CHANCE) {
+            self.set_cell(&water, CellElement::Air);
+            self.set_cell(&above, CellElement::Plant(height + 1));
+            // the new tip waits a tick before it grows any further
+            let index = self.index(&above);
+            self.moved[index] = true;
+        } else {
+            // a plant that could grow has to keep trying
+            let center = Vector2::new(coordinate.x as i64, coordinate.y as i64);
+            self.mark_dirty(center, center);
+        }
+    }
+
+    /// Thins smoke out by a tick and lets it rise, returning where it ended up
+    fn drift(&mut self, coordinate: Coordinate, lifetime: u8) -> Coordinate {
+        // smoke that reaches the top escapes instead of piling up against it
+        if lifetime <= 1 || self.offset(&coordinate, &-self.down()).is_none() {
+            self.set_cell(&coordinate, CellElement::Air);
+            return coordinate;
+        }
+
+        self.set_cell(&coordinate, CellElement::Smoke(lifetime - 1));
+        self.rise(coordinate)
+    }
+
+    /// Moves a gas up a cell, or sideways when something is in the way, returning where it
+    /// ended up
+    fn rise(&mut self, coordinate: Coordinate) -> Coordinate {
+        let up = -self.down();
+        let across = perpendicular(&up);
+        let mut diagonals = up.unit_neighbors().unwrap();
+        let mut sides = [across, -across];
+        if self.rng.gen_bool(0.5) {
+            diagonals.swap(0, 1);
+            sides.swap(0, 1);
+        }
+        for step in [up, diagonals[0], diagonals[1], sides[0], sides[1]] {
+            let Some(next) = self.offset(&coordinate, &step) else {
+                continue;
+            };
+            if self.get_cell(&next) == Some(CellElement::Air) {
+                self.swap_cells(&coordinate, &next);
+                return next;
+            }
+        }
+
+        coordinate
+    }
+
+    /// Where a cell moving at `velocity` ends up this tick, stopping it at the edges of the
+    /// world so the path there never leaves it, and which way it would have gone on past them
+    fn destination(
+        &self,
+        coordinate: &Coordinate,
+        velocity: &mut Vector2<f32>,
+    ) -> (Coordinate, Vector2<i32>) {
+        // rounding toward the floor lets cells fall slower than a cell a tick, whichever way
+        // gravity points
+        let down = self.down();
+        let round = |position: f32, down: i32| {
+            if down > 0 {
+                position.ceil()
+            } else {
+                position.floor()
+            }
+        };
+        let mut x = round(coordinate.x as f32 + velocity.x, down.x);
+        let mut y = round(coordinate.y as f32 + velocity.y, down.y);
+
+        // stop at the edges; only solid ones also stop the cell from moving on through them
+        let mut overflow = Vector2::zeros();
+        if x < 0.0 || x > (self.width - 1) as f32 {
+            overflow.x = x.signum() as i32;
+            x = x.clamp(0.0, (self.width - 1) as f32);
+            if self.boundary == BoundaryMode::Solid {
+                velocity.x = 0.0;
+            }
+        }
+
+        if y < 0.0 || y > (self.height - 1) as f32 {
+            overflow.y = y.signum() as i32;
+            y = y.clamp(0.0, (self.height - 1) as f32);
+            if self.boundary == BoundaryMode::Solid {
+                velocity.y = 0.0;
+            }
+        }
+
+        (Coordinate::new(x as u32, y as u32), overflow)
+    }
+
+    /// Flows a liquid that can't fall any further sideways, or failing that evens it out with
+    /// a lower column up to `spread` cells along the row. Returns where it went if it moved
+    /// sideways
+    fn update_fluid(
+        &mut self,
+        cell: &CellElement,
+        coordinate: Coordinate,
+        spread: u32,
+    ) -> Option<Coordinate> {
+        if let Some(side) = self.spread(cell, coordinate) {
+            return Some(side);
+        }
+        self.equalize(cell, coordinate, spread);
+        None
+    }
+
+    /// Moves a fluid one cell to a random side it can displace, returning where it went
+    fn spread(&mut self, cell: &CellElement, coordinate: Coordinate) -> Option<Coordinate> {
+        let across = perpendicular(&self.down());
+        let mut sides = [across, -across];
+        if self.rng.gen_bool(0.5) {
+            sides.swap(0, 1);
+        }
+
+        for side in sides {
+            let Some(side_coordinate) = self.offset(&coordinate, &side) else {
+                continue;
+            };
+            if self.can_displace(cell, &side_coordinate)
+                && !self.contested(&coordinate, &side_coordinate)
+            {
+                self.swap_cells(&coordinate, &side_coordinate);
+                return Some(side_coordinate);
+            }
+        }
+
+        None
+    }
+
+    /// Moves the top of the liquid column standing on `coordinate` over to a column joined to
+    /// it along the row whose surface is at least two cells lower and at most `spread` cells
+    /// away, so pools connected underneath level out instead of only spreading sideways
+    fn equalize(&mut self, cell: &CellElement, coordinate: Coordinate, spread: u32) {
+        let kind = cell.kind();
+        let down = self.down();
+        let depth = self.column_length(kind, coordinate, -down) as i32;
+        if depth == 0 {
+            // only the bottom of a column has anything to push with, so the surface wakes
+            // the liquid beneath it whenever it changes
+            let below = self.column_length(kind, coordinate, down) as i32;
+            if below > 0 {
+                let center = coordinate.cast::<i32>();
+                let first = (center + down).cast::<i64>();
+                let last = (center + down * below).cast::<i64>();
+                self.mark_dirty(first.inf(&last), first.sup(&last));
+            }
+            return;
+        }
+
+        let surface = (coordinate.cast::<i32>() - down * depth).map(|value| value as u32);
+        let across = perpendicular(&down);
+        let mut sides = [across, -across];
+        if self.rng.gen_bool(0.5) {
+            sides.swap(0, 1);
+        }
+        for side in sides {
+            for step in 1..=spread as i32 {
+                // pools are only joined through the liquid itself
+                let Some(neighbor) = self.offset(&coordinate, &(side * step)) else {
+                    break;
+                };
+                if self.get_cell(&neighbor).map(|cell| cell.kind()) != Some(kind) {
+                    break;
+                }
+
+                let height = self.column_length(kind, neighbor, -down) as i32 + 1;
+                let Some(target) = self.offset(&neighbor, &(-down * height)) else {
+                    continue;
+                };
+                if height < depth && self.get_cell(&target) == Some(CellElement::Air) {
+                    self.swap_cells(&surface, &target);
+                    // keep pushing until the levels even out
+                    let center = Vector2::new(coordinate.x as i64, coordinate.y as i64);
+                    self.mark_dirty(center, center);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// How many cells of `kind` are lined up next to `coordinate`, going from it by `direction`
+    fn column_length(
+        &self,
+        kind: ElementKind,
+        coordinate: Coordinate,
+        direction: Vector2<i32>,
+    ) -> u32 {
+        let mut length = 0;
+        let mut next = self.offset(&coordinate, &direction);
+        while let Some(coordinate) = next {
+            if self.get_cell(&coordinate).map(|cell| cell.kind()) != Some(kind) {
+                break;
+            }
+            length += 1;
+            next = self.offset(&coordinate, &direction);
+        }
+        length
+    }
+
+    /// First solid cell on the line from `from` to `to`, `to` included, or `None` if
+    /// nothing blocks it. `from` itself is never counted
+    pub fn raycast(&self, from: Coordinate, to: Coordinate) -> Option<Coordinate> {
+        path(&from, &to)
+            .into_iter()
+            .find(|coordinate| matches!(self.get_cell(coordinate), Some(cell) if cell.is_solid()))
+    }
+
+    /// Whether a cell rests on the floor or on something other than air, wherever gravity
+    /// makes the floor
+    pub fn is_supported(&self, coordinate: &Coordinate) -> bool {
+        match self.offset(coordinate, &self.down()) {
+            Some(below) => self.get_cell(&below) != Some(CellElement::Air),
+            None => true,
+        }
+    }
+
+    /// The step a falling cell takes, which is gravity rounded to one of the eight
+    /// neighbors; straight down when there's no gravity
+    fn down(&self) -> Vector2<i32> {
+        if self.gravity == Vector2::zeros() {
+            return Vector2::new(0, -1);
+        }
+        self.gravity.normalize().map(|value| value.round() as i32)
+    }
+
+    /// The cell `step` away from `coordinate`, if it's inside the world
+    fn offset(&self, coordinate: &Coordinate, step: &Vector2<i32>) -> Option<Coordinate> {
+        let next = coordinate.cast::<i64>() + step.cast::<i64>();
+        if next.x < 0 || next.y < 0 {
+            return None;
+        }
+        let next = next.map(|value| value as u32);
+        self.in_bounds(&next).then_some(next)
+    }
+
+    /// Whether any of the eight surrounding cells holds something other than air
+    fn touches_anything(&self, coordinate: &Coordinate) -> bool {
+        self.neighbors(coordinate)
+            .into_iter()
+            .flatten()
+            .any(|(_, cell)| cell != CellElement::Air)
+    }
+
+    /// Deletes isolated specks that are neither supported nor touching anything
+    pub fn remove_floating(&mut self) {
+        let floating: Vec<Coordinate> = self
+            .iter_cells()
+            .filter(|(coordinate, cell)| {
+                *cell != CellElement::Air
+                    && !self.is_supported(coordinate)
+                    && !self.touches_anything(coordinate)
+            })
+            .map(|(coordinate, _)| coordinate)
+            .collect();
+
+        for coordinate in floating.iter() {
+            self.set_cell(coordinate, CellElement::Air);
+        }
+    }
+
+    /// Turns every cell into air
+    pub fn clear(&mut self) {
+        self.fill(CellElement::Air);
+    }
+
+    /// Turns every cell into `element`
+    pub fn fill(&mut self, element: CellElement) {
+        self.cells.fill(element);
+        self.mutations += self.cells.len() as u64;
+        self.mark_all_dirty();
+        self.mark_all_changed();
+    }
+
+    /// Lines the bottom row and both side columns with stone, making a bucket for things to
+    /// pile up in instead of spreading out to the edges
+    pub fn add_walls(&mut self) {
+        for x in 0..self.width {
+            self.set_cell(&Coordinate::new(x, 0), CellElement::Stone);
+        }
+        for y in 1..self.height {
+            self.set_cell(&Coordinate::new(0, y), CellElement::Stone);
+            self.set_cell(&Coordinate::new(self.width - 1, y), CellElement::Stone);
+        }
+    }
+
+    /// Swaps two cells and flags both as moved so neither is stepped again this tick
+    pub fn swap_cells(&mut self, a_coordinate: &Coordinate, b_coordinate: &Coordinate) {
+        if let Some(a) = self.get_cell(a_coordinate) {
+            if let Some(b) = self.get_cell(b_coordinate) {
+                self.set_cell(a_coordinate, b);
+                self.set_cell(b_coordinate, a);
+                let (a_index, b_index) = (self.index(a_coordinate), self.index(b_coordinate));
+                self.moved[a_index] = true;
+                self.moved[b_index] = true;
+                // heat travels with whatever is in the cell
+                if self.temperature[a_index] != self.temperature[b_index] {
+                    self.temperature.swap(a_index, b_index);
+                    self.heat_settled = false;
+                }
+            }
+        }
+    }
+
+    pub fn get_cell(&self, coordinate: &Coordinate) -> Option<CellElement> {
+        if self.in_bounds(coordinate) {
+            return Some(self.cells[self.index(coordinate)]);
+        }
+        None
+    }
+
+    /// The eight cells around `coordinate` along with where they are, `None` for the ones
+    /// past the edges of the world
+    pub fn neighbors(&self, coordinate: &Coordinate) -> [Option<(Coordinate, CellElement)>; 8] {
+        const OFFSETS: [(i32, i32); 8] = [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ];
+        OFFSETS.map(|(dx, dy)| {
+            let neighbor = self.offset(coordinate, &Vector2::new(dx, dy))?;
+            Some((neighbor, self.cells[self.index(&neighbor)]))
+        })
+    }
+
+    pub fn get_temperature(&self, coordinate: &Coordinate) -> Option<f32> {
+        if self.in_bounds(coordinate) {
+            return Some(self.temperature[self.index(coordinate)]);
+        }
+        None
+    }
+
+    pub fn set_temperature(&mut self, coordinate: &Coordinate, temperature: f32) {
+        assert!(
+            self.in_bounds(coordinate),
+            "{coordinate} is outside the world"
+        );
+        let index = self.index(coordinate);
+        self.temperature[index] = temperature;
+        self.heat_settled = false;
+    }
+
+    /// Raises a cell to at least `temperature`
+    fn heat(&mut self, coordinate: &Coordinate, temperature: f32) {
+        let index = self.index(coordinate);
+        if self.temperature[index] < temperature {
+            self.temperature[index] = temperature;
+            self.heat_settled = false;
+        }
+    }
+
+    pub fn set_cell(&mut self, coordinate: &Coordinate, cell: CellElement) {
+        assert!(
+            self.in_bounds(coordinate),
+            "{coordinate} is outside the world"
+        );
+        let index = self.index(coordinate);
+        if self.cells[index] == cell {
+            return;
+        }
+
+        self.cells[index] = cell;
+        self.mutations += 1;
+        let center = coordinate.cast::<i64>();
+        self.mark_changed(center, center);
+    }
+
+    /// Redraws the rows of the cells between `min` and `max`, both inclusive, and steps them
+    /// and their neighbors next update
+    fn mark_changed(&mut self, min: Vector2<i64>, max: Vector2<i64>) {
+        let (first, last) = (min.y as u32, max.y as u32);
+        self.changed_rows = Some(match self.changed_rows {
+            Some((changed_first, changed_last)) => {
+                (changed_first.min(first), changed_last.max(last))
+            }
+            None => (first, last),
+        });
+        // neighbors may have been resting on or against the old cells
+        self.mark_dirty(min - Vector2::repeat(1), max + Vector2::repeat(1));
+    }
+}
+
+/// `direction` turned a quarter turn anticlockwise
+fn perpendicular(direction: &Vector2<i32>) -> Vector2<i32> {
+    Vector2::new(-direction.y, direction.x)
+}
+
+/// A random number for the cell at `index` that only depends on `salt`, so every thread draws
+/// the same ones
+fn mix(salt: u64, index: usize) -> u64 {
+    // splitmix64's finalizer
+    let mut mixed = salt ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    mixed ^ (mixed >> 31)
+}
+
+/// Inverse of `World::index` for a world `width` cells wide
+fn index_coordinate(index: usize, width: u32) -> Coordinate {
+    Coordinate::new(index as u32 % width, index as u32 / width)
+}
+
+/// Picks a random shade for a new grain of sand
+pub fn sand_shade(rng: &mut impl Rng) -> u8 {
+    rng.gen_range(0..=MAX_SAND_SHADE)
+}
+
+/// Writes the temperatures row `y` of `temperature` diffuses to into `next`
+fn diffuse_row(temperature: &[f32], width: usize, y: usize, next: &mut [f32]) -> HeatChange {
+    let height = temperature.len() / width;
+    let mut change = HeatChange::default();
+    for (x, next) in next.iter_mut().enumerate() {
+        let index = y * width + x;
+        let current = temperature[index];
+
+        let mut flow = 0.0;
+        if x > 0 {
+            flow += temperature[index - 1] - current;
+        }
+        if x + 1 < width {
+            flow += temperature[index + 1] - current;
+        }
+        if y > 0 {
+            flow += temperature[index - width] - current;
+        }
+        if y + 1 < height {
+            flow += temperature[index + width] - current;
+        }
+
+        let difference = HEAT_DIFFUSION * flow;
+        *next = current + difference;
+        change.largest = change.largest.max(difference.abs());
+        if difference.abs() >= HEAT_SETTLED {
+            let cell = Vector2::new(x as i64, y as i64);
+            change = change.merge(HeatChange {
+                largest: 0.0,
+                changed: Some((cell, cell)),
+            });
+        }
+    }
+    change
+}
+
+/// Adds the push or pull of each of `forces` that reaches a cell at `position` to its
+/// `velocity`, one after the other
+pub fn apply_forces<'a>(
+    mut velocity: Vector2<f32>,
+    position: &Vector2<f32>,
+    forces: impl Iterator<Item = &'a Force>,
+) -> Vector2<f32> {
+    for force in forces {
+        let difference = force.position - position;
+        let distance_squared = difference.magnitude_squared();
+        if distance_squared > 0.0
+            && distance_squared >= force.min_distance_squared
+            && distance_squared <= force.max_distance_squared
+        {
+            let pull = difference.normalize()
+                * (force.strength / distance_squared.max(MIN_FALLOFF_DISTANCE_SQUARED));
+            velocity += match force.kind {
+                ForceKind::Attract => pull,
+                ForceKind::Repel => -pull,
+            };
+        }
+    }
+    velocity
+}
+
+/// Forces sorted into square buckets over the world by the cells they can reach, so each cell
+/// only checks the forces that might reach it instead of all of them
+pub struct ForceGrid<'a> {
+    forces: &'a [Force],
+    columns: u32,
+    /// indices into `forces`, row by row starting at the bottom; they stay in the order the
+    /// forces were given so they add up to exactly the same velocity
+    buckets: Vec<Vec<usize>>,
+}
+
+impl<'a> ForceGrid<'a> {
+    pub fn new(world: &World, forces: &'a [Force]) -> Self {
+        let columns = world.width.div_ceil(FORCE_BUCKET_SIZE);
+        let rows = world.height.div_ceil(FORCE_BUCKET_SIZE);
+        let mut buckets = vec![Vec::new(); (columns * rows) as usize];
+
+        let last = Vector2::new(world.width as i64 - 1, world.height as i64 - 1);
+        for (index, force) in forces.iter().enumerate() {
+            let (min, max) = world.reach(force);
+            let (min, max) = (min.sup(&Vector2::zeros()), max.inf(&last));
+            if min.x > max.x || min.y > max.y {
+                continue;
+            }
+
+            let bucket = |bound: i64| bound as u32 / FORCE_BUCKET_SIZE;
+            for row in bucket(min.y)..=bucket(max.y) {
+                for column in bucket(min.x)..=bucket(max.x) {
+                    buckets[(row * columns + column) as usize].push(index);
+                }
+            }
+        }
+
+        Self {
+            forces,
+            columns,
+            buckets,
+        }
+    }
+
+    /// Forces that might reach `coordinate`, in their original order
+    pub fn near(&self, coordinate: &Coordinate) -> impl Iterator<Item = &'a Force> + '_ {
+        let row = coordinate.y / FORCE_BUCKET_SIZE;
+        let column = coordinate.x / FORCE_BUCKET_SIZE;
+        self.buckets[(row * self.columns + column) as usize]
+            .iter()
+            .map(|&index| &self.forces[index])
+    }
+}
+
+/// Pushes or pulls the grains between its two radii with inverse-square falloff
+pub struct Force {
+    pub position: Vector2<f32>,
+    pub strength: f32,
+    pub kind: ForceKind,
+    pub min_distance_squared: f32,
+    pub max_distance_squared: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForceKind {
+    /// pulls grains toward the force
+    Attract,
+    /// blasts grains away from the force
+    Repel,
+}
+
+/// Drops an element into a cell whenever it's empty, like a faucet
+#[derive(Clone, Copy)]
+pub struct Emitter {
+    pub coordinate: Coordinate,
+    /// what it spawns, sand unless changed
+    pub element: ElementKind,
+    /// grains per tick, fractions carry over to the next tick
+    pub rate: f32,
+    /// tint given to spawned sand, `None` uses the palette
+    pub color: Option<[u8; 4]>,
+    /// normalized direction grains are launched in
+    pub direction: Vector2<f32>,
+    /// average launch speed
+    pub pressure: f32,
+    /// how far the launch speed may randomly stray from `pressure`
+    pub pressure_variance: f32,
+    /// largest random speed added across `direction`, so cells fan out instead of leaving
+    /// in a straight line
+    pub spread: f32,
+    accumulated: f32,
+}
+
+impl Emitter {
+    /// Creates an emitter that drops grains of sand without launching them
+    pub fn new(coordinate: Coordinate, rate: f32, color: Option<[u8; 4]>) -> Self {
+        Self {
+            coordinate,
+            element: ElementKind::Sand,
+            rate,
+            color,
+            direction: Vector2::new(0.0, -1.0),
+            pressure: 0.0,
+            pressure_variance: 0.0,
+            spread: 0.0,
+            accumulated: 0.0,
+        }
+    }
+
+    fn spawn_velocity(&self, rng: &mut impl Rng) -> Vector2<f32> {
+        let mut speed = self.pressure;
+        if self.pressure_variance > 0.0 {
+            speed += rng.gen_range(-self.pressure_variance..=self.pressure_variance);
+        }
+        let mut velocity = self.direction * speed;
+        if self.spread > 0.0 {
+            let across = Vector2::new(-self.direction.y, self.direction.x);
+            velocity += across * rng.gen_range(-self.spread..=self.spread);
+        }
+        velocity
+    }
+}
+
+/// Attracts all sand with inverse-square falloff; unlike `Force` it has no inner cutoff
+#[derive(Clone, Copy)]
+pub struct GravityWell {
+    pub center: Vector2<f32>,
+    pub strength: f32,
+}
+
+/// translated from https://gist.github.com/DavidMcLaughlin208/60e69e698e3858617c322d80a8f174e2
+/// TODO optimize
+fn path(start: &Vector2<u32>, end: &Vector2<u32>) -> Vec<Vector2<u32>> {
+    if start == end {
+        return Vec::new();
+    }
+
+    let mut path = Vec::<Vector2<u32>>::new();
+
+    let matrix_x1 = start.x as i32;
+    let matrix_y1 = start.y as i32;
+    let matrix_x2 = end.x as i32;
+    let matrix_y2 = end.y as i32;
+
+    let x_diff = matrix_x1 - matrix_x2;
+    let y_diff = matrix_y1 - matrix_y2;
+
+    let x_diff_is_larger = x_diff.abs() > y_diff.abs();
+
+    let x_modifier: i32 = if x_diff < 0 { 1 } else { -1 };
+    let y_modifier: i32 = if y_diff < 0 { 1 } else { -1 };
+
+    let longer_side_length = x_diff.abs().max(y_diff.abs());
+    let shorter_side_length = x_diff.abs().min(y_diff.abs());
+
+    let slope = if shorter_side_length == 0 || longer_side_length == 0 {
+        0.0
+    } else {
+        shorter_side_length as f32 / longer_side_length as f32
+    };
+
+    let mut shorter_side_increase: i32;
+
+    for i in 1..=longer_side_length {
+        shorter_side_increase = (slope * i as f32).round() as i32;
+        let x_increase: i32;
+        let y_increase: i32;
+
+        if x_diff_is_larger {
+            x_increase = i;
+            y_increase = shorter_side_increase;
+        } else {
+            y_increase = i;
+            x_increase = shorter_side_increase;
+        }
+
+        let current_y = matrix_y1 + (y_increase * y_modifier);
+        let current_x = matrix_x1 + (x_increase * x_modifier);
+
+        //println!("x: {current_x}; y: {current_y}");
+        path.push(Vector2::new(current_x as u32, current_y as u32));
+    }
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector2;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::palette::Palette;
+
+    use super::{
+        apply_forces, diffuse_row, path, BoundaryMode, CellElement, Coordinate, DirtyRect,
+        ElementCounts, ElementKind, Emitter, Force, ForceGrid, ForceKind, HeatChange,
+        PackedVelocity, Unit, World, AMBIENT_TEMPERATURE, FIRE_LIFETIME, GRAVITY, HEAT_SETTLED,
+        ICE_TEMPERATURE, MAX_SAND_SHADE, MAX_VELOCITY, PLANT_MAX_HEIGHT, SMOKE_LIFETIME,
+        WORLD_SIZE,
+    };
+
+    fn empty_world() -> World {
+        World::new(WORLD_SIZE, WORLD_SIZE)
+    }
+
+    fn sand_coordinates(world: &World) -> Vec<Coordinate> {
+        world
+            .iter_cells()
+            .filter(|(_, cell)| matches!(cell, CellElement::Sand(..)))
+            .map(|(coordinate, _)| coordinate)
+            .collect()
+    }
+
+    fn test_path(from: Vector2<u32>, to: Vector2<u32>, between: Vec<Vector2<u32>>) {
+        let path = path(&from, &to);
+        if from == to {
+            assert_eq!(path.len(), 0);
+            return;
+        }
+        assert_eq!(path.len(), between.len() + 1);
+        for i in 0..between.len() {
+            assert_eq!(path[i], between[i]);
+        }
+        assert_eq!(path[between.len()], to);
+    }
+
+    #[test]
+    fn path_works() {
+        test_path(Vector2::new(0, 0), Vector2::new(0, 0), vec![]);
+        test_path(Vector2::new(0, 0), Vector2::new(1, 0), vec![]);
+        test_path(Vector2::new(0, 0), Vector2::new(0, 1), vec![]);
+        test_path(Vector2::new(0, 0), Vector2::new(1, 1), vec![]);
+        test_path(Vector2::new(1, 0), Vector2::new(0, 0), vec![]);
+        test_path(Vector2::new(1, 1), Vector2::new(0, 0), vec![]);
+        test_path(Vector2::new(0, 1), Vector2::new(0, 0), vec![]);
+
+        test_path(
+            Vector2::new(0, 0),
+            Vector2::new(0, 3),
+            vec![Vector2::new(0, 1), Vector2::new(0, 2)],
+        );
+
+        test_path(
+            Vector2::new(0, 0),
+            Vector2::new(3, 3),
+            vec![Vector2::new(1, 1), Vector2::new(2, 2)],
+        );
+    }
+
+    #[test]
+    fn unit_neighbors_are_distinct() {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let unit = Vector2::new(x, y);
+                let Some([a, b]) = unit.unit_neighbors() else {
+                    assert_eq!(unit, Vector2::zeros());
+                    continue;
+                };
+                assert_ne!(a, b, "neighbors of {unit} repeat");
+            }
+        }
+    }
+
+    #[test]
+    fn worlds_can_be_any_size() {
+        let mut world = World::new(20, 10);
+        assert_eq!(world.pixels().len(), 20 * 10 * 4);
+        assert!(world.get_cell(&Coordinate::new(20, 0)).is_none());
+        assert!(world.get_cell(&Coordinate::new(0, 10)).is_none());
+        assert_eq!(world.position(&Coordinate::new(10, 5)), Vector2::zeros());
+
+        world.set_cell(
+            &Coordinate::new(19, 9),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+        for _ in 0..20 {
+            world.update(&[]);
+        }
+        assert_eq!(sand_coordinates(&world), vec![Coordinate::new(19, 0)]);
+    }
+
+    #[test]
+    fn fill_and_clear_cover_every_cell() {
+        let mut world = World::default();
+        world.fill(CellElement::Sand(PackedVelocity::ZERO, None, 0));
+        assert_eq!(
+            sand_coordinates(&world).len(),
+            (WORLD_SIZE * WORLD_SIZE) as usize
+        );
+
+        world.clear();
+        // the default world starts with a sand bed, so compare against a blank one
+        assert!(world.pixels() == World::new(WORLD_SIZE, WORLD_SIZE).pixels());
+    }
+
+    #[test]
+    fn paths_stay_inside_the_world() {
+        let world = World::new(20, 10);
+        let corners = [
+            Coordinate::new(0, 0),
+            Coordinate::new(19, 0),
+            Coordinate::new(0, 9),
+            Coordinate::new(19, 9),
+        ];
+
+        for start in corners {
+            for (x, y) in [
+                (-50.0, 0.0),
+                (50.0, 0.0),
+                (0.0, -50.0),
+                (0.0, 50.0),
+                (-7.0, 33.0),
+            ] {
+                let mut velocity = Vector2::new(x, y);
+                let (destination, _) = world.destination(&start, &mut velocity);
+                for step in path(&start, &destination) {
+                    assert!(world.in_bounds(&step), "{start} stepped out to {step}");
+                }
+            }
+        }
+    }
+
+    /// A world without gravity holding one grain at the right edge, moving right
+    fn grain_at_the_right_edge(boundary: BoundaryMode) -> World {
+        let mut world = World::new(20, 20);
+        world.set_gravity(Vector2::zeros());
+        world.set_boundary(boundary);
+        world.set_cell(
+            &Coordinate::new(19, 10),
+            CellElement::Sand(Vector2::new(2.0, 0.0).into(), None, 0),
+        );
+        world
+    }
+
+    #[test]
+    fn solid_edges_stop_grains() {
+        let mut world = grain_at_the_right_edge(BoundaryMode::Solid);
+        world.update(&[]);
+        assert_eq!(sand_coordinates(&world), vec![Coordinate::new(19, 10)]);
+        assert_eq!(
+            world.get_cell(&Coordinate::new(19, 10)),
+            Some(CellElement::Sand(PackedVelocity::ZERO, None, 0))
+        );
+    }
+
+    #[test]
+    fn wrapping_edges_bring_grains_back_on_the_other_side() {
+        let mut world = grain_at_the_right_edge(BoundaryMode::Wrap);
+        world.update(&[]);
+        assert_eq!(sand_coordinates(&world), vec![Coordinate::new(0, 10)]);
+
+        // and it carries on from there
+        world.update(&[]);
+        let grain = sand_coordinates(&world)[0];
+        assert!(grain.x > 0 && grain.x < 10, "ended up at {grain}");
+        assert_eq!(grain.y, 10);
+    }
+
+    #[test]
+    fn void_edges_swallow_grains() {
+        let mut world = grain_at_the_right_edge(BoundaryMode::Void);
+        assert_eq!(world.counts().sand, 1);
+        world.update(&[]);
+        assert_eq!(world.counts().sand, 0);
+
+        // with gravity the floor lets everything resting on it fall through
+        let mut world = World::new(20, 20);
+        world.set_cell(
+            &Coordinate::new(5, 0),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+        world.set_cell(
+            &Coordinate::new(6, 3),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+        world.set_boundary(BoundaryMode::Void);
+        for _ in 0..20 {
+            world.update(&[]);
+        }
+        assert_eq!(world.counts().air, 400);
+    }
+
+    #[test]
+    fn changed_rows_cover_every_changed_cell() {
+        let mut world = World::new(20, 10);
+        assert_eq!(world.changed_rows(), Some((0, 9)));
+
+        world.clear_changed();
+        world.update(&[]);
+        assert_eq!(world.changed_rows(), None);
+
+        world.set_cell(&Coordinate::new(3, 6), CellElement::Stone);
+        world.set_cell(&Coordinate::new(15, 2), CellElement::Stone);
+        assert_eq!(world.changed_rows(), Some((2, 6)));
+
+        world.clear_changed();
+        world.set_palette(Palette::night());
+        assert_eq!(world.changed_rows(), Some((0, 9)));
+    }
+
+    #[test]
+    fn write_pixels_reuses_the_buffer() {
+        let world = World::default();
+        let mut pixels = vec![7; 10];
+        world.write_pixels(&mut pixels);
+        assert!(pixels == world.pixels());
+
+        let capacity = pixels.capacity();
+        world.write_pixels(&mut pixels);
+        assert_eq!(pixels.capacity(), capacity);
+        assert!(pixels == world.pixels());
+    }
+
+    #[test]
+    fn pixels_scaled_replicates_cells() {
+        let world = World::default();
+        let pixels = world.pixels();
+        let (scaled, width, height) = world.pixels_scaled(2);
+
+        assert_eq!(width, WORLD_SIZE * 2);
+        assert_eq!(height, WORLD_SIZE * 2);
+        assert_eq!(scaled.len(), pixels.len() * 4);
+
+        for y in 0..WORLD_SIZE as usize {
+            for x in 0..WORLD_SIZE as usize {
+                let cell = &pixels[(y * WORLD_SIZE as usize + x) * 4..][..4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let index = ((y * 2 + dy) * width as usize + x * 2 + dx) * 4;
+                    assert_eq!(&scaled[index..index + 4], cell);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oscillating_grain_settles() {
+        let mut world = empty_world();
+        world.set_cell(
+            &Coordinate::new(10, 0),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+
+        // pulls the grain back and forth across the boundary between two cells; it's always
+        // half a cell away, so the falloff is clamped to a full cell
+        let forces = [Force {
+            position: world.position(&Coordinate::new(10, 0)) + Vector2::new(0.5, 0.0),
+            strength: 1.2,
+            kind: ForceKind::Attract,
+            min_distance_squared: 0.0,
+            max_distance_squared: 100.0,
+        }];
+
+        let mut history = Vec::new();
+        for _ in 0..40 {
+            world.update(&forces);
+            history.push(sand_coordinates(&world)[0]);
+        }
+
+        // without damping the grain keeps bouncing between x = 10 and x = 11
+        let settled = history[20];
+        assert!(history[20..]
+            .iter()
+            .all(|coordinate| *coordinate == settled));
+    }
+
+    #[test]
+    fn repulsive_force_pushes_grains_away() {
+        let mut world = empty_world();
+        let grain = Coordinate::new(100, 100);
+        world.set_cell(&grain, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+
+        let forces = [Force {
+            position: world.position(&grain) + Vector2::new(3.0, 0.0),
+            strength: 50.0,
+            kind: ForceKind::Repel,
+            min_distance_squared: 0.0,
+            max_distance_squared: 100.0,
+        }];
+        world.update(&forces);
+
+        let coordinate = sand_coordinates(&world)[0];
+        let Some(CellElement::Sand(velocity, ..)) = world.get_cell(&coordinate) else {
+            panic!("the grain disappeared");
+        };
+        assert!(velocity.unpack().x < 0.0);
+        assert!(coordinate.x < grain.x);
+    }
+
+    #[test]
+    fn explosions_blast_grains_outward() {
+        let mut world = empty_world();
+        let center = Coordinate::new(100, 100);
+        let sand = CellElement::Sand(PackedVelocity::ZERO, None, 0);
+        let left = Coordinate::new(95, 100);
+        let above = Coordinate::new(100, 104);
+        let far = Coordinate::new(130, 100);
+        for coordinate in [left, above, far] {
+            world.set_cell(&coordinate, sand);
+        }
+        world.set_cell(&Coordinate::new(103, 100), CellElement::Stone);
+
+        world.explode(&center, 10, 50.0);
+
+        let velocity = |coordinate| world.get_cell(&coordinate).unwrap().velocity().unwrap();
+        assert!(velocity(left).x < 0.0);
+        assert_eq!(velocity(left).y, 0.0);
+        assert!(velocity(above).y > 0.0);
+        // closer grains are thrown harder
+        assert!(velocity(above).y > -velocity(left).x);
+        assert_eq!(velocity(far), Vector2::zeros());
+        assert_eq!(
+            world.get_cell(&Coordinate::new(103, 100)),
+            Some(CellElement::Stone)
+        );
+    }
+
+    #[test]
+    fn velocity_is_clamped_under_extreme_forces() {
+        let mut world = empty_world();
+        world.set_cell(
+            &Coordinate::new(150, 150),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+
+        let forces = [Force {
+            position: Vector2::new(0.0, 50.0),
+            strength: 1e9,
+            kind: ForceKind::Attract,
+            min_distance_squared: 0.0,
+            max_distance_squared: f32::MAX,
+        }];
+        for _ in 0..20 {
+            world.update(&forces);
+            let coordinate = sand_coordinates(&world)[0];
+            let velocity = world.get_cell(&coordinate).unwrap().velocity().unwrap();
+            assert!(velocity.magnitude() <= MAX_VELOCITY + 1e-3);
+        }
+    }
+
+    #[test]
+    fn force_grid_matches_checking_every_force() {
+        let world = World::new(100, 100);
+        let mut rng = StdRng::seed_from_u64(3);
+        // some of them only partly overlap the world, or not at all
+        let forces: Vec<Force> = (0..50)
+            .map(|_| Force {
+                position: Vector2::new(rng.gen_range(-70.0..70.0), rng.gen_range(-70.0..70.0)),
+                strength: rng.gen_range(1.0..100.0),
+                kind: if rng.gen_bool(0.5) {
+                    ForceKind::Attract
+                } else {
+                    ForceKind::Repel
+                },
+                min_distance_squared: rng.gen_range(0.0..10.0),
+                max_distance_squared: rng.gen_range(10.0..900.0),
+            })
+            .collect();
+
+        let grid = ForceGrid::new(&world, &forces);
+        let velocity = Vector2::new(0.5, -1.0);
+        for y in 0..world.height() {
+            for x in 0..world.width() {
+                let coordinate = Coordinate::new(x, y);
+                let position = world.position(&coordinate);
+                assert_eq!(
+                    apply_forces(velocity, &position, grid.near(&coordinate)),
+                    apply_forces(velocity, &position, forces.iter())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn packed_velocities_round_trip_closely() {
+        for velocity in [
+            Vector2::new(0.0, -0.2),
+            Vector2::new(1.2345, -6.789),
+            Vector2::new(-MAX_VELOCITY, MAX_VELOCITY),
+            Vector2::new(0.0004, -0.0004),
+        ] {
+            let unpacked = PackedVelocity::pack(velocity).unpack();
+            assert!(
+                (unpacked - velocity).amax() <= 0.0005,
+                "{velocity} became {unpacked}"
+            );
+        }
+        assert!(std::mem::size_of::<CellElement>() <= 12);
+    }
+
+    #[test]
+    fn grains_float_without_gravity() {
+        let mut world = empty_world();
+        let grain = Coordinate::new(150, 200);
+        world.set_cell(&grain, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+        world.set_gravity(Vector2::zeros());
+
+        for _ in 0..20 {
+            world.update(&[]);
+        }
+        assert_eq!(sand_coordinates(&world), vec![grain]);
+
+        world.set_gravity(GRAVITY);
+        world.update(&[]);
+        assert!(sand_coordinates(&world)[0].y < grain.y);
+    }
+
+    #[test]
+    fn fast_grains_move_once_per_tick() {
+        // falling moves into rows the scan already passed, rising moves into rows it has yet
+        // to reach, and either way the grain only gets one step
+        for (start, velocity, end) in [(100, -20.0, 80), (50, 20.0, 69)] {
+            let mut world = empty_world();
+            world.set_cell(
+                &Coordinate::new(150, start),
+                CellElement::Sand(Vector2::new(0.0, velocity).into(), None, 0),
+            );
+
+            world.update(&[]);
+            assert_eq!(sand_coordinates(&world), vec![Coordinate::new(150, end)]);
+        }
+    }
+
+    #[test]
+    fn wind_drift_levels_off_at_wind_over_friction() {
+        let mut world = World::new(WORLD_SIZE, 20);
+        world.set_gravity(Vector2::zeros());
+        let start = Coordinate::new(10, 10);
+        world.set_cell(&start, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+        world.set_wind(Vector2::new(0.5, 0.0));
+        let terminal = world.wind().x / world.air_friction();
+
+        let mut drift = Vec::new();
+        for _ in 0..100 {
+            world.update(&[]);
+            let coordinate = sand_coordinates(&world)[0];
+            if let Some(CellElement::Sand(velocity, ..)) = world.get_cell(&coordinate) {
+                drift.push(velocity.unpack());
+            }
+        }
+
+        let end = sand_coordinates(&world)[0];
+        assert!(end.x > start.x + 50);
+        assert_eq!(end.y, start.y);
+        // levelled off instead of speeding up forever
+        for velocity in drift[40..].iter() {
+            assert!((velocity.x - terminal).abs() < 0.01, "{velocity}");
+            assert_eq!(velocity.y, 0.0);
+        }
+    }
+
+    #[test]
+    fn gravity_well_pulls_closer_grains_harder() {
+        let mut world = empty_world();
+        let far = Coordinate::new(100, 200);
+        let near = Coordinate::new(130, 150);
+        world.set_cell(&far, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+        world.set_cell(&near, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+        world.add_gravity_well(Vector2::zeros(), 1000.0);
+
+        world.update(&[]);
+
+        let mut velocities = Vec::new();
+        for coordinate in sand_coordinates(&world) {
+            if let Some(CellElement::Sand(velocity, ..)) = world.get_cell(&coordinate) {
+                velocities.push(velocity.unpack());
+            }
+        }
+
+        // the near grain is on the lower row so it's found first
+        let (near_velocity, far_velocity) = (velocities[0], velocities[1]);
+        assert!(near_velocity.x > 0.0);
+        assert!(far_velocity.x > 0.0);
+        assert!(near_velocity.magnitude() > far_velocity.magnitude());
+    }
+
+    #[test]
+    fn element_color_changes_pixels() {
+        let mut world = World::default();
+        world.set_cell(
+            &Coordinate::new(0, 0),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+        world.set_element_color(ElementKind::Sand, [255, 0, 0, 255]);
+
+        let pixels = world.pixels();
+        assert_eq!(&pixels[..4], &[255, 0, 0, 255]);
+        assert_eq!(world.element_color(ElementKind::Sand), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn set_palette_recolors_everything() {
+        let mut world = World::new(2, 1);
+        world.set_cell(
+            &Coordinate::new(1, 0),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+
+        let night = Palette::night();
+        world.set_palette(night);
+
+        let mut expected = night.color(ElementKind::Air).to_vec();
+        expected.extend_from_slice(&night.color(ElementKind::Sand));
+        assert_eq!(world.pixels(), expected);
+        assert!(world.palette() == night);
+    }
+
+    #[test]
+    fn sand_shade_darkens_the_grain() {
+        let mut world = World::new(2, 1);
+        world.set_element_color(ElementKind::Sand, [200, 100, 10, 255]);
+        world.set_cell(
+            &Coordinate::new(0, 0),
+            CellElement::Sand(PackedVelocity::ZERO, None, 20),
+        );
+        world.set_cell(
+            &Coordinate::new(1, 0),
+            CellElement::Sand(PackedVelocity::ZERO, Some([50, 50, 50, 128]), 20),
+        );
+
+        assert_eq!(world.pixels(), vec![180, 80, 0, 255, 30, 30, 30, 128]);
+    }
+
+    #[test]
+    fn water_emitter_fills_a_column() {
+        let mut world = World::new(1, 12);
+        let mut emitter = Emitter::new(Coordinate::new(0, 11), 1.0, None);
+        emitter.element = ElementKind::Water;
+        world.add_emitter(emitter);
+
+        for _ in 0..60 {
+            world.update(&[]);
+        }
+
+        for y in 0..world.height() {
+            let cell = world.get_cell(&Coordinate::new(0, y));
+            assert_eq!(cell.map(|cell| cell.kind()), Some(ElementKind::Water));
+        }
+    }
+
+    #[test]
+    fn spawned_grains_get_different_shades() {
+        let mut world = World::new(3, 40);
+        world.rng = StdRng::seed_from_u64(3);
+        world.add_emitter(Emitter::new(Coordinate::new(1, 39), 1.0, None));
+        for _ in 0..20 {
+            world.update(&[]);
+        }
+
+        let mut shades = Vec::new();
+        for coordinate in sand_coordinates(&world) {
+            let Some(CellElement::Sand(_, _, shade)) = world.get_cell(&coordinate) else {
+                unreachable!();
+            };
+            assert!(shade <= MAX_SAND_SHADE);
+            shades.push(shade);
+        }
+        assert_eq!(shades.len(), 20);
+        assert!(shades.iter().any(|shade| *shade != shades[0]));
+    }
+
+    #[test]
+    fn remove_floating_only_removes_specks() {
+        let mut world = empty_world();
+        let grain = CellElement::Sand(PackedVelocity::ZERO, None, 0);
+
+        // a small pile with a grain stuck to its side
+        for x in 10..15 {
+            world.set_cell(&Coordinate::new(x, 0), grain);
+        }
+        world.set_cell(&Coordinate::new(12, 1), grain);
+        world.set_cell(&Coordinate::new(15, 1), grain);
+
+        let speck = Coordinate::new(50, 100);
+        world.set_cell(&speck, grain);
+
+        world.remove_floating();
+
+        assert_eq!(world.get_cell(&speck), Some(CellElement::Air));
+        assert_eq!(sand_coordinates(&world).len(), 7);
+    }
+
+    #[test]
+    fn emitters_tag_grains_with_their_color() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+
+        let mut world = empty_world();
+        world.add_emitter(Emitter::new(Coordinate::new(100, 200), 1.0, Some(red)));
+        world.add_emitter(Emitter::new(Coordinate::new(200, 200), 0.5, Some(blue)));
+
+        for _ in 0..10 {
+            world.update(&[]);
+        }
+
+        let mut counts = [0, 0];
+        for coordinate in sand_coordinates(&world) {
+            let Some(CellElement::Sand(_, color, _)) = world.get_cell(&coordinate) else {
+                unreachable!();
+            };
+            match coordinate.x {
+                100 => {
+                    assert_eq!(color, Some(red));
+                    counts[0] += 1;
+                }
+                200 => {
+                    assert_eq!(color, Some(blue));
+                    counts[1] += 1;
+                }
+                _ => panic!("grain strayed to {coordinate}"),
+            }
+        }
+        assert_eq!(counts, [10, 5]);
+    }
+
+    #[test]
+    fn water_spreads_instead_of_piling_up() {
+        let mut world = empty_world();
+        for y in 0..5 {
+            world.set_cell(
+                &Coordinate::new(150, y),
+                CellElement::Water(PackedVelocity::ZERO),
+            );
+        }
+
+        for _ in 0..50 {
+            world.update(&[]);
+        }
+
+        let mut water = Vec::new();
+        for x in 0..WORLD_SIZE {
+            for y in 0..WORLD_SIZE {
+                let coordinate = Coordinate::new(x, y);
+                if let Some(CellElement::Water(_)) = world.get_cell(&coordinate) {
+                    water.push(coordinate);
+                }
+            }
+        }
+        assert_eq!(water.len(), 5);
+        assert!(water.iter().all(|coordinate| coordinate.y == 0));
+    }
+
+    #[test]
+    fn water_settles_in_a_one_cell_pocket() {
+        let mut world = empty_world();
+        for x in 0..WORLD_SIZE {
+            world.set_cell(
+                &Coordinate::new(x, 0),
+                CellElement::Sand(PackedVelocity::ZERO, None, 0),
+            );
+            if x != 100 {
+                world.set_cell(
+                    &Coordinate::new(x, 1),
+                    CellElement::Sand(PackedVelocity::ZERO, None, 0),
+                );
+            }
+        }
+        world.set_cell(
+            &Coordinate::new(100, 20),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+
+        for _ in 0..40 {
+            world.update(&[]);
+        }
+
+        let pocket = Coordinate::new(100, 1);
+        for _ in 0..10 {
+            world.update(&[]);
+            assert_eq!(
+                world.get_cell(&pocket),
+                Some(CellElement::Water(PackedVelocity::ZERO))
+            );
+        }
+    }
+
+    #[test]
+    fn water_levels_out_in_a_u_shaped_container() {
+        let mut world = World::new(20, 30);
+        let stone = |world: &mut World, x: u32, y: u32| {
+            world.set_cell(&Coordinate::new(x, y), CellElement::Stone);
+        };
+        // two arms, 2..=4 and 10..=12, joined by a channel along the bottom row
+        for x in 1..=13 {
+            stone(&mut world, x, 0);
+        }
+        for y in 1..30 {
+            stone(&mut world, 1, y);
+            stone(&mut world, 13, y);
+            if y > 1 {
+                for x in 5..=9 {
+                    stone(&mut world, x, y);
+                }
+            }
+        }
+
+        // the channel starts out full so the arms are joined, then only the left arm is filled
+        for x in 2..=12 {
+            world.set_cell(
+                &Coordinate::new(x, 1),
+                CellElement::Water(PackedVelocity::ZERO),
+            );
+        }
+        let spout = Coordinate::new(3, 29);
+        let mut poured = 0;
+        for _ in 0..1000 {
+            if poured < 60 && world.get_cell(&spout) == Some(CellElement::Air) {
+                world.set_cell(&spout, CellElement::Water(PackedVelocity::ZERO));
+                poured += 1;
+            }
+            world.update(&[]);
+        }
+        assert_eq!(poured, 60);
+
+        let level = |xs: std::ops::RangeInclusive<u32>| {
+            xs.flat_map(|x| (0..30).map(move |y| Coordinate::new(x, y)))
+                .filter(|coordinate| {
+                    matches!(world.get_cell(coordinate), Some(CellElement::Water(_)))
+                })
+                .map(|coordinate| coordinate.y)
+                .max()
+                .unwrap()
+        };
+        let (left, right) = (level(2..=4), level(10..=12));
+        assert!(right > 1);
+        assert!(
+            left.abs_diff(right) <= 1,
+            "left arm at {left}, right arm at {right}"
+        );
+    }
+
+    #[test]
+    fn wider_spread_reaches_a_lower_column_farther_away() {
+        // six full columns of water, then one only a cell deep at the far end of the row
+        let flowed = |spread: u32| {
+            let mut world = World::new(7, 10);
+            let water = CellElement::Water(PackedVelocity::ZERO);
+            for x in 0..6 {
+                for y in 0..4 {
+                    world.set_cell(&Coordinate::new(x, y), water);
+                }
+            }
+            world.set_cell(&Coordinate::new(6, 0), water);
+
+            assert_eq!(
+                world.update_fluid(&water, Coordinate::new(0, 0), spread),
+                None
+            );
+            world.get_cell(&Coordinate::new(6, 1)) == Some(water)
+        };
+
+        assert!(!flowed(3));
+        assert!(flowed(6));
+        assert!(ElementKind::Water.spread() > ElementKind::Lava.spread());
+    }
+
+    #[test]
+    fn walls_make_a_bucket() {
+        let mut world = World::new(10, 8);
+        world.add_walls();
+
+        for (coordinate, cell) in world.iter_cells() {
+            let wall = coordinate.y == 0 || coordinate.x == 0 || coordinate.x == 9;
+            let expected = if wall {
+                CellElement::Stone
+            } else {
+                CellElement::Air
+            };
+            assert_eq!(cell, expected, "{coordinate}");
+        }
+    }
+
+    #[test]
+    fn neighbors_stop_at_the_edges() {
+        let mut world = World::new(10, 10);
+        world.set_cell(&Coordinate::new(1, 1), CellElement::Stone);
+
+        let corner: Vec<_> = world
+            .neighbors(&Coordinate::new(0, 0))
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(corner.len(), 3);
+        assert!(corner.contains(&(Coordinate::new(1, 1), CellElement::Stone)));
+        assert!(corner.contains(&(Coordinate::new(1, 0), CellElement::Air)));
+        assert!(corner.contains(&(Coordinate::new(0, 1), CellElement::Air)));
+
+        let far_corner = world.neighbors(&Coordinate::new(9, 9));
+        assert_eq!(far_corner.iter().flatten().count(), 3);
+        let middle = world.neighbors(&Coordinate::new(5, 5));
+        assert!(middle.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn raycast_sees_through_air_and_sand() {
+        let mut world = World::new(20, 20);
+        world.set_cell(
+            &Coordinate::new(5, 5),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+        assert_eq!(
+            world.raycast(Coordinate::new(0, 0), Coordinate::new(19, 19)),
+            None
+        );
+    }
+
+    #[test]
+    fn raycast_stops_at_the_first_wall() {
+        let mut world = World::new(20, 20);
+        for y in 0..20 {
+            world.set_cell(&Coordinate::new(8, y), CellElement::Stone);
+            world.set_cell(&Coordinate::new(12, y), CellElement::Stone);
+        }
+        assert_eq!(
+            world.raycast(Coordinate::new(2, 4), Coordinate::new(18, 4)),
+            Some(Coordinate::new(8, 4))
+        );
+        assert_eq!(
+            world.raycast(Coordinate::new(18, 4), Coordinate::new(2, 4)),
+            Some(Coordinate::new(12, 4))
+        );
+    }
+
+    #[test]
+    fn raycast_sees_a_wall_at_its_end() {
+        let mut world = World::new(20, 20);
+        world.set_cell(&Coordinate::new(6, 5), CellElement::Stone);
+        world.set_cell(&Coordinate::new(15, 5), CellElement::Stone);
+
+        // right next to the start
+        assert_eq!(
+            world.raycast(Coordinate::new(5, 5), Coordinate::new(6, 5)),
+            Some(Coordinate::new(6, 5))
+        );
+        assert_eq!(
+            world.raycast(Coordinate::new(9, 5), Coordinate::new(15, 5)),
+            Some(Coordinate::new(15, 5))
+        );
+        // but not at its start
+        assert_eq!(
+            world.raycast(Coordinate::new(6, 5), Coordinate::new(9, 5)),
+            None
+        );
+        assert_eq!(
+            world.raycast(Coordinate::new(6, 5), Coordinate::new(6, 5)),
+            None
+        );
+    }
+
+    #[test]
+    fn sand_sinks_through_water() {
+        let mut world = empty_world();
+        for y in 0..10 {
+            world.set_cell(
+                &Coordinate::new(150, y),
+                CellElement::Water(PackedVelocity::ZERO),
+            );
+        }
+        world.set_cell(
+            &Coordinate::new(150, 10),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+
+        for _ in 0..40 {
+            world.update(&[]);
+        }
+
+        let sand = sand_coordinates(&world);
+        assert_eq!(sand.len(), 1);
+        assert_eq!(sand[0].y, 0);
+    }
+
+    #[test]
+    fn stone_holds_up_sand_and_water() {
+        let mut world = empty_world();
+        for x in 140..=160 {
+            world.set_cell(&Coordinate::new(x, 100), CellElement::Stone);
+        }
+        world.set_cell(
+            &Coordinate::new(150, 120),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+        world.set_cell(
+            &Coordinate::new(150, 101),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+
+        for _ in 0..30 {
+            world.update(&[]);
+        }
+
+        for x in 140..=160 {
+            assert_eq!(
+                world.get_cell(&Coordinate::new(x, 100)),
+                Some(CellElement::Stone)
+            );
+        }
+        let sand = sand_coordinates(&world);
+        assert_eq!(sand.len(), 1);
+        assert_eq!(sand[0].y, 101);
+    }
+
+    #[test]
+    fn sand_is_conserved_through_a_seeded_run() {
+        let mut world = World::with_seed(11);
+        world.clear();
+        let mut rng = StdRng::seed_from_u64(3);
+        for x in 100..200 {
+            for y in 100..120 {
+                world.set_cell(
+                    &Coordinate::new(x, y),
+                    CellElement::new(ElementKind::Sand, &mut rng),
+                );
+            }
+            world.set_cell(
+                &Coordinate::new(x, 60),
+                CellElement::Acid(PackedVelocity::ZERO, 2),
+            );
+            world.set_cell(
+                &Coordinate::new(x, 80),
+                CellElement::Water(PackedVelocity::ZERO),
+            );
+        }
+        let start = world.counts().sand;
+
+        // checked here as well as in debug builds' updates, so release builds check it too
+        let mut dissolved = 0;
+        for _ in 0..150 {
+            let before = world.counts().sand;
+            world.update(&[]);
+            assert_eq!(world.counts().sand + world.removed_sand, before);
+            dissolved += world.removed_sand;
+        }
+
+        assert!(dissolved > 0, "the sand never reached the acid");
+        // each of the 100 acid cells dissolves at most its potency of cells
+        assert!(dissolved <= 100 * 2);
+        assert_eq!(world.counts().sand, start - dissolved);
+    }
+
+    #[test]
+    fn seeded_worlds_play_out_the_same() {
+        let run = |seed| {
+            let mut world = World::with_seed(seed);
+            let mut emitter = Emitter::new(Coordinate::new(150, 200), 1.0, None);
+            emitter.pressure = 2.0;
+            emitter.pressure_variance = 1.0;
+            world.add_emitter(emitter);
+            for _ in 0..60 {
+                world.update(&[]);
+            }
+            world.hash()
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn hash_sees_velocities_the_pixels_leave_out() {
+        let mut world = World::new(10, 10);
+        let grain = Coordinate::new(5, 5);
+        world.set_cell(&grain, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+        let (hash, pixels) = (world.hash(), world.pixels());
+
+        let moving = PackedVelocity::pack(Vector2::new(0.0, -1.0));
+        world.set_cell(&grain, CellElement::Sand(moving, None, 0));
+        assert_eq!(world.pixels(), pixels);
+        assert_ne!(world.hash(), hash);
+    }
+
+    #[test]
+    fn symmetric_pyramid_collapses_symmetrically() {
+        let mut world = empty_world();
+        // steeper than sand can rest at, so the sides slide off
+        for row in 0..40 {
+            let half_width = (39 - row) / 3;
+            for x in 150 - half_width..=150 + half_width {
+                world.set_cell(
+                    &Coordinate::new(x, 5 + row),
+                    CellElement::Sand(PackedVelocity::ZERO, None, 0),
+                );
+            }
+        }
+
+        for _ in 0..60 {
+            world.update(&[]);
+        }
+
+        let sand = sand_coordinates(&world);
+        let left = sand.iter().map(|coordinate| coordinate.x).min().unwrap();
+        let right = sand.iter().map(|coordinate| coordinate.x).max().unwrap();
+        let mean = sand
+            .iter()
+            .map(|coordinate| coordinate.x as f32)
+            .sum::<f32>()
+            / sand.len() as f32;
+        assert!(left < 150 - 13, "the pile never slid");
+        assert!((150 - left).abs_diff(right - 150) <= 1);
+        assert!((mean - 150.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn steeper_repose_makes_taller_narrower_piles() {
+        // height of the tallest column and number of columns with any sand
+        let pile = |steepness: u32| {
+            let mut world = World::new(61, 60);
+            world.rng = StdRng::seed_from_u64(9);
+            world.set_repose_steepness(steepness);
+            let source = Coordinate::new(30, 55);
+            let mut poured = 0;
+            for _ in 0..2000 {
+                if poured < 120 && world.get_cell(&source) == Some(CellElement::Air) {
+                    world.set_cell(&source, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+                    poured += 1;
+                }
+                world.update(&[]);
+            }
+            assert_eq!(world.counts().sand, 120);
+
+            let sand = sand_coordinates(&world);
+            let height = (0..61)
+                .map(|x| sand.iter().filter(|grain| grain.x == x).count())
+                .max()
+                .unwrap();
+            let width = (0..61)
+                .filter(|&x| sand.iter().any(|grain| grain.x == x))
+                .count();
+            (height, width)
+        };
+
+        let (gentle_height, gentle_width) = pile(1);
+        let (steep_height, steep_width) = pile(3);
+        assert!(
+            steep_height > gentle_height,
+            "{steep_height} vs {gentle_height}"
+        );
+        assert!(
+            steep_width < gentle_width,
+            "{steep_width} vs {gentle_width}"
+        );
+    }
+
+    /// A seeded world stepped in parallel, with sand falling onto water and oil next to a hot
+    /// patch and a force pulling on it
+    fn parallel_world() -> (World, Vec<Force>) {
+        let mut world = World::new(60, 45);
+        world.reseed(9);
+        world.set_parallel(true);
+        for y in 0..8 {
+            for x in 20..60 {
+                let cell = if x < 40 {
+                    CellElement::Water(PackedVelocity::ZERO)
+                } else {
+                    CellElement::Oil(PackedVelocity::ZERO)
+                };
+                world.set_cell(&Coordinate::new(x, y), cell);
+            }
+        }
+        for y in 20..40 {
+            for x in 5..35 {
+                let grain = CellElement::Sand(PackedVelocity::ZERO, None, (x % 7) as u8);
+                world.set_cell(&Coordinate::new(x, y), grain);
+            }
+        }
+        for x in 0..15 {
+            world.set_temperature(&Coordinate::new(x, 0), 400.0);
+        }
+        let forces = vec![Force {
+            position: Vector2::new(5.0, -10.0),
+            strength: 50.0,
+            kind: ForceKind::Attract,
+            min_distance_squared: 4.0,
+            max_distance_squared: 100.0,
+        }];
+        (world, forces)
+    }
+
+    #[test]
+    fn parallel_update_matches_a_single_thread_bit_for_bit() {
+        let run = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let (mut world, forces) = parallel_world();
+                for _ in 0..100 {
+                    world.update(&forces);
+                }
+                let temperatures: Vec<u32> = world
+                    .temperature
+                    .iter()
+                    .map(|temperature| temperature.to_bits())
+                    .collect();
+                (world.hash(), temperatures, world.mutations(), world.dirty)
+            })
+        };
+
+        let serial = run(1);
+        assert_eq!(serial, run(4));
+        assert_ne!(serial.0, parallel_world().0.hash());
+    }
+
+    #[test]
+    fn parallel_update_piles_sand_on_the_floor() {
+        let mut world = World::new(40, 40);
+        world.set_parallel(true);
+        for y in 20..40 {
+            for x in 18..22 {
+                world.set_cell(
+                    &Coordinate::new(x, y),
+                    CellElement::Sand(PackedVelocity::ZERO, None, 0),
+                );
+            }
+        }
+
+        for _ in 0..300 {
+            world.update(&[]);
+        }
+
+        let sand = sand_coordinates(&world);
+        assert_eq!(sand.len(), 80);
+        assert!(sand.iter().all(|grain| world.is_supported(grain)));
+        // it slid off to both sides instead of standing as a column
+        assert!(sand.iter().any(|grain| grain.x < 17));
+        assert!(sand.iter().any(|grain| grain.x > 22));
+        assert!(sand.iter().all(|grain| grain.y < 20));
+        assert_eq!(world.dirty, None);
+    }
+
+    #[test]
+    fn sideways_gravity_piles_sand_against_the_right_wall() {
+        let mut world = World::new(30, 21);
+        world.set_gravity(Vector2::new(0.2, 0.0));
+        // a bar along gravity, like a column of sand under normal gravity
+        for x in 0..16 {
+            world.set_cell(
+                &Coordinate::new(x, 10),
+                CellElement::Sand(PackedVelocity::ZERO, None, 0),
+            );
+        }
+
+        for _ in 0..200 {
+            world.update(&[]);
+        }
+
+        let sand = sand_coordinates(&world);
+        assert_eq!(sand.len(), 16);
+        assert!(sand.iter().all(|grain| world.is_supported(grain)));
+        // a pile lying on its side, tallest against the wall and around the row it fell along
+        let column = |x| sand.iter().filter(|grain| grain.x == x).count();
+        assert!(column(29) > column(28));
+        assert!(column(28) > column(27));
+        assert_eq!(column(26), 0);
+        let mean = sand.iter().map(|grain| grain.y as f32).sum::<f32>() / sand.len() as f32;
+        assert!((mean - 10.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn settled_world_stops_changing() {
+        let mut world = World::new(40, 40);
+        for x in 10..30 {
+            world.set_cell(
+                &Coordinate::new(x, 30),
+                CellElement::Sand(PackedVelocity::ZERO, None, 0),
+            );
+        }
+
+        for _ in 0..100 {
+            world.update(&[]);
+        }
+        assert!(world.dirty.is_none());
+
+        let mutations = world.mutations();
+        world.update(&[]);
+        assert_eq!(world.mutations(), mutations);
+    }
+
+    #[test]
+    fn dirty_rect_follows_changes() {
+        let mut world = World::new(40, 40);
+        world.update(&[]);
+        assert!(world.dirty.is_none());
+
+        world.set_cell(
+            &Coordinate::new(20, 10),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+        assert_eq!(
+            world.dirty,
+            Some(DirtyRect {
+                min: Coordinate::new(19, 9),
+                max: Coordinate::new(21, 11),
+            })
+        );
+
+        // the grain falls, dragging the rect down with it
+        world.update(&[]);
+        let dirty = world.dirty.unwrap();
+        assert_eq!(dirty.min.y, 8);
+        assert!(dirty.max.y <= 11);
+    }
+
+    #[test]
+    fn iter_cells_visits_every_cell_once() {
+        let mut world = empty_world();
+        let corner = Coordinate::new(WORLD_SIZE - 1, 3);
+        world.set_cell(&corner, CellElement::Stone);
+
+        let mut visited = 0;
+        for (coordinate, cell) in world.iter_cells() {
+            assert_eq!(world.get_cell(&coordinate), Some(cell));
+            visited += 1;
+        }
+        assert_eq!(visited, (WORLD_SIZE * WORLD_SIZE) as usize);
+    }
+
+    #[test]
+    fn iter_cells_mut_changes_cells_in_place() {
+        let mut world = World::new(6, 5);
+        world.clear_changed();
+        for (coordinate, cell) in world.iter_cells_mut() {
+            if coordinate.y == 0 {
+                *cell = CellElement::Stone;
+            }
+        }
+
+        assert_eq!(world.counts().stone, 6);
+        assert_eq!(
+            world.get_cell(&Coordinate::new(5, 0)),
+            Some(CellElement::Stone)
+        );
+        assert_eq!(
+            world.get_cell(&Coordinate::new(0, 1)),
+            Some(CellElement::Air)
+        );
+        assert_eq!(world.changed_rows(), Some((0, 4)));
+    }
+
+    #[test]
+    fn cells_report_their_kind_whatever_their_payload() {
+        let moving = PackedVelocity::pack(Vector2::new(1.5, -2.0));
+        let cells = [
+            (CellElement::Air, ElementKind::Air),
+            (
+                CellElement::Sand(moving, Some([1, 2, 3, 4]), 9),
+                ElementKind::Sand,
+            ),
+            (CellElement::Water(moving), ElementKind::Water),
+            (CellElement::Stone, ElementKind::Stone),
+            (CellElement::Fire(FIRE_LIFETIME), ElementKind::Fire),
+            (CellElement::Wood, ElementKind::Wood),
+            (CellElement::Smoke(1), ElementKind::Smoke),
+            (CellElement::Acid(moving, 2), ElementKind::Acid),
+            (CellElement::Steam, ElementKind::Steam),
+            (CellElement::Ice, ElementKind::Ice),
+            (CellElement::Plant(3), ElementKind::Plant),
+            (CellElement::Lava(moving), ElementKind::Lava),
+            (CellElement::Oil(moving), ElementKind::Oil),
+        ];
+
+        for (cell, kind) in cells {
+            assert_eq!(cell.kind(), kind);
+            assert_eq!(cell.density(), kind.density());
+            assert_eq!(cell.is_liquid(), kind.is_liquid());
+        }
+        assert_eq!(
+            cells.map(|(_, kind)| kind),
+            ElementKind::ALL,
+            "every kind should be covered"
+        );
+    }
+
+    #[test]
+    fn counts_cover_every_cell() {
+        let mut world = World::new(8, 4);
+        world.set_cell(&Coordinate::new(0, 0), CellElement::Stone);
+        world.set_cell(&Coordinate::new(1, 0), CellElement::Stone);
+        world.set_cell(
+            &Coordinate::new(2, 0),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+        world.set_cell(&Coordinate::new(3, 3), CellElement::Ice);
+
+        let counts = world.counts();
+        assert_eq!(
+            counts,
+            ElementCounts {
+                air: 28,
+                stone: 2,
+                water: 1,
+                ice: 1,
+                ..ElementCounts::default()
+            }
+        );
+        let total: usize = ElementKind::ALL.iter().map(|&kind| counts.get(kind)).sum();
+        assert_eq!(total, 32);
+    }
+
+    #[test]
+    fn update_advances_the_tick() {
+        let mut world = empty_world();
+        assert_eq!(world.tick(), 0);
+        for _ in 0..3 {
+            world.update(&[]);
+        }
+        assert_eq!(world.tick(), 3);
+    }
+
+    #[test]
+    fn fire_rises_and_burns_out() {
+        let mut world = World::new(20, 60);
+        let start = Coordinate::new(10, 5);
+        world.set_cell(&start, CellElement::Fire(FIRE_LIFETIME));
+
+        for _ in 0..FIRE_LIFETIME {
+            world.update(&[]);
+            for y in 0..world.height() {
+                for x in 0..world.width() {
+                    if let Some(CellElement::Fire(_)) = world.get_cell(&Coordinate::new(x, y)) {
+                        assert!(y >= start.y, "fire sank to {y}");
+                    }
+                }
+            }
+        }
+
+        let kinds: Vec<_> = (0..world.height())
+            .flat_map(|y| (0..world.width()).map(move |x| Coordinate::new(x, y)))
+            .filter_map(|coordinate| world.get_cell(&coordinate))
+            .filter(|cell| *cell != CellElement::Air)
+            .map(|cell| cell.kind())
+            .collect();
+        assert_eq!(kinds, vec![ElementKind::Smoke]);
+    }
+
+    #[test]
+    fn smoke_rises_and_clears_at_the_top() {
+        let mut world = World::new(5, 20);
+        world.set_cell(&Coordinate::new(2, 0), CellElement::Smoke(SMOKE_LIFETIME));
+
+        for _ in 0..19 {
+            world.update(&[]);
+        }
+        assert_eq!(
+            world.get_cell(&Coordinate::new(2, 19)),
+            Some(CellElement::Smoke(SMOKE_LIFETIME - 19))
+        );
+
+        // well before it would have thinned out on its own
+        world.update(&[]);
+        assert!(world.pixels() == World::new(5, 20).pixels());
+    }
+
+    #[test]
+    fn fire_consumes_a_wood_wall() {
+        let mut world = World::new(20, 20);
+        for y in 0..5 {
+            for x in 10..13 {
+                world.set_cell(&Coordinate::new(x, y), CellElement::Wood);
+            }
+        }
+        world.set_cell(&Coordinate::new(9, 0), CellElement::Fire(FIRE_LIFETIME));
+
+        let wood_left = |world: &World| {
+            (0..world.height())
+                .flat_map(|y| (0..world.width()).map(move |x| Coordinate::new(x, y)))
+                .filter(|coordinate| world.get_cell(coordinate) == Some(CellElement::Wood))
+                .count()
+        };
+
+        // each tick the fire reaches one ring further, and the far corner is 4 cells away
+        world.update(&[]);
+        assert_eq!(
+            world.get_cell(&Coordinate::new(10, 0)),
+            Some(CellElement::Fire(FIRE_LIFETIME))
+        );
+        for _ in 1..4 {
+            assert!(wood_left(&world) > 0);
+            world.update(&[]);
+        }
+        assert_eq!(wood_left(&world), 0);
+
+        for _ in 0..FIRE_LIFETIME as u32 + SMOKE_LIFETIME as u32 {
+            world.update(&[]);
+        }
+        assert!(world.pixels() == World::new(20, 20).pixels());
+    }
+
+    #[test]
+    fn plant_grows_by_drinking_the_water_beside_it() {
+        let mut world = World::new(3, 10);
+        world.rng = StdRng::seed_from_u64(5);
+        world.set_cell(
+            &Coordinate::new(0, 0),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+        world.set_cell(&Coordinate::new(1, 0), CellElement::Plant(0));
+        world.set_cell(
+            &Coordinate::new(2, 0),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+
+        for _ in 0..400 {
+            world.update(&[]);
+        }
+
+        // every drop grew the plant a cell, and with none left it stopped
+        let counts = world.counts();
+        assert_eq!(counts.water, 0);
+        assert_eq!(counts.plant, 3);
+        assert_eq!(
+            world.get_cell(&Coordinate::new(1, 2)),
+            Some(CellElement::Plant(2))
+        );
+    }
+
+    #[test]
+    fn fire_and_plants_grow_against_gravity() {
+        let mut world = World::new(3, 20);
+        world.rng = StdRng::seed_from_u64(5);
+        world.set_gravity(-GRAVITY);
+        world.set_cell(
+            &Coordinate::new(0, 19),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+        world.set_cell(&Coordinate::new(1, 19), CellElement::Plant(0));
+
+        for _ in 0..400 {
+            world.update(&[]);
+        }
+        assert_eq!(
+            world.get_cell(&Coordinate::new(1, 18)),
+            Some(CellElement::Plant(1))
+        );
+
+        let mut world = World::new(20, 60);
+        world.set_gravity(-GRAVITY);
+        let start = Coordinate::new(10, 54);
+        world.set_cell(&start, CellElement::Fire(FIRE_LIFETIME));
+        for _ in 0..FIRE_LIFETIME - 1 {
+            world.update(&[]);
+            let fire = (0..world.height())
+                .flat_map(|y| (0..world.width()).map(move |x| Coordinate::new(x, y)))
+                .find(|coordinate| matches!(world.get_cell(coordinate), Some(CellElement::Fire(_))))
+                .unwrap();
+            assert!(fire.y <= start.y, "fire sank to {}", fire.y);
+        }
+    }
+
+    #[test]
+    fn plant_stops_growing_at_its_height_limit() {
+        let mut world = World::new(2, 30);
+        world.set_cell(&Coordinate::new(0, 0), CellElement::Plant(PLANT_MAX_HEIGHT));
+        world.set_cell(
+            &Coordinate::new(1, 0),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+
+        for _ in 0..200 {
+            world.update(&[]);
+        }
+
+        assert_eq!(world.counts().plant, 1);
+        assert_eq!(world.counts().water, 1);
+    }
+
+    #[test]
+    fn acid_dissolves_stone_and_is_used_up() {
+        let mut world = World::new(20, 20);
+        let stone = Coordinate::new(10, 0);
+        world.set_cell(&stone, CellElement::Stone);
+        world.set_cell(
+            &Coordinate::new(10, 1),
+            CellElement::Acid(PackedVelocity::ZERO, 1),
+        );
+
+        // well past the point a 1 in 5 chance a tick fails to come up
+        for _ in 0..200 {
+            world.update(&[]);
+        }
+
+        assert!(world.pixels() == World::new(20, 20).pixels());
+    }
+
+    #[test]
+    fn heat_spreads_without_being_lost() {
+        let mut world = World::new(20, 20);
+        let hot = Coordinate::new(0, 10);
+        world.set_temperature(&hot, 1000.0);
+
+        let total_heat = |world: &World| {
+            (0..world.height())
+                .flat_map(|y| (0..world.width()).map(move |x| Coordinate::new(x, y)))
+                .map(|coordinate| world.get_temperature(&coordinate).unwrap() as f64)
+                .sum::<f64>()
+        };
+        let before = total_heat(&world);
+
+        for _ in 0..5 {
+            world.update(&[]);
+        }
+        for x in 1..=3 {
+            let temperature = world.get_temperature(&Coordinate::new(x, 10)).unwrap();
+            assert!(temperature > AMBIENT_TEMPERATURE);
+        }
+        assert!(world.get_temperature(&hot).unwrap() < 1000.0);
+        assert!((total_heat(&world) - before).abs() < 0.1);
+    }
+
+    /// Diffuses `temperature` into `next` one row after the other
+    fn diffuse_serially(temperature: &[f32], width: usize, next: &mut [f32]) -> HeatChange {
+        next.chunks_mut(width)
+            .enumerate()
+            .map(|(y, next)| diffuse_row(temperature, width, y, next))
+            .fold(HeatChange::default(), HeatChange::merge)
+    }
+
+    /// A world with a patch of random temperatures in it
+    fn patchy_world(width: u32, height: u32, seed: u64) -> World {
+        let mut world = World::new(width, height);
+        let mut rng = StdRng::seed_from_u64(seed);
+        for y in height / 4..height / 2 {
+            for x in width / 4..width / 2 {
+                world.set_temperature(&Coordinate::new(x, y), rng.gen_range(-50.0..700.0));
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn parallel_diffusion_matches_serial() {
+        let mut world = patchy_world(64, 48, 11);
+        let mut serial = vec![0.0; world.temperature.len()];
+        let change = diffuse_serially(&world.temperature, 64, &mut serial);
+
+        world.dirty = None;
+        world.diffuse_heat();
+
+        let bits = |temperatures: &[f32]| -> Vec<u32> {
+            temperatures
+                .iter()
+                .map(|temperature| temperature.to_bits())
+                .collect()
+        };
+        assert_eq!(bits(&world.temperature), bits(&serial));
+        assert_eq!(world.heat_settled, change.largest < HEAT_SETTLED);
+        // only the patch and the ring of cells around it changed
+        assert_eq!(
+            world.dirty,
+            Some(DirtyRect {
+                min: Coordinate::new(15, 11),
+                max: Coordinate::new(32, 24),
+            })
+        );
+        assert_eq!(
+            change.changed,
+            Some((Vector2::new(15, 11), Vector2::new(32, 24)))
+        );
+    }
+
+    #[test]
+    fn fire_heats_its_surroundings() {
+        let mut world = World::new(20, 20);
+        world.set_cell(&Coordinate::new(10, 0), CellElement::Fire(FIRE_LIFETIME));
+        for _ in 0..5 {
+            world.update(&[]);
+        }
+        assert!(world.get_temperature(&Coordinate::new(12, 0)).unwrap() > AMBIENT_TEMPERATURE);
+    }
+
+    #[test]
+    fn water_on_a_hot_floor_boils() {
+        let mut world = World::new(20, 20);
+        for x in 0..20 {
+            let floor = Coordinate::new(x, 0);
+            world.set_cell(&floor, CellElement::Stone);
+            world.set_temperature(&floor, 500.0);
+        }
+        world.set_cell(
+            &Coordinate::new(10, 1),
+            CellElement::Water(PackedVelocity::ZERO),
+        );
+
+        let boiled = (0..50).any(|_| {
+            world.update(&[]);
+            world.cells.contains(&CellElement::Steam)
+        });
+        assert!(boiled);
+    }
+
+    #[test]
+    fn ice_melts_inward_from_a_hot_source() {
+        // a single row, so the ice only warms up from the side the heat comes from
+        let mut world = World::new(12, 1);
+        let source = Coordinate::new(0, 0);
+        world.set_cell(&source, CellElement::Stone);
+        world.set_temperature(&source, 1000.0);
+        for x in 1..12 {
+            let coordinate = Coordinate::new(x, 0);
+            world.set_cell(&coordinate, CellElement::Ice);
+            world.set_temperature(&coordinate, ICE_TEMPERATURE);
+        }
+
+        let mut melted_at = [None; 12];
+        for tick in 0..5000 {
+            world.update(&[]);
+            for (x, melted) in melted_at.iter_mut().enumerate().skip(1) {
+                if melted.is_none()
+                    && world.get_cell(&Coordinate::new(x as u32, 0)) != Some(CellElement::Ice)
+                {
+                    *melted = Some(tick);
+                }
+            }
+        }
+
+        // the edge melts while the far end is still frozen, then the front works its way in
+        let melted_at: Vec<u32> = melted_at[1..].iter().map(|tick| tick.unwrap()).collect();
+        assert!(melted_at[0] < melted_at[10]);
+        assert!(melted_at.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn lava_in_water_sets_into_stone_and_boils_it() {
+        let mut world = World::new(20, 20);
+        for y in 0..4 {
+            for x in 0..20 {
+                world.set_cell(
+                    &Coordinate::new(x, y),
+                    CellElement::Water(PackedVelocity::ZERO),
+                );
+            }
+        }
+        let lava = Coordinate::new(10, 1);
+        world.set_cell(&lava, CellElement::Lava(PackedVelocity::ZERO));
+
+        world.update(&[]);
+        assert_eq!(world.get_cell(&lava), Some(CellElement::Stone));
+        for _ in 0..5 {
+            world.update(&[]);
+        }
+        assert!(world.counts().steam > 0);
+    }
+
+    #[test]
+    fn lava_stays_molten_in_the_open_and_lights_wood() {
+        let mut world = World::new(20, 20);
+        let wood = Coordinate::new(11, 0);
+        world.set_cell(&wood, CellElement::Wood);
+        world.set_cell(
+            &Coordinate::new(10, 0),
+            CellElement::Lava(PackedVelocity::ZERO),
+        );
+
+        let mut lit = false;
+        for _ in 0..100 {
+            world.update(&[]);
+            lit |= matches!(world.get_cell(&wood), Some(CellElement::Fire(..)));
+        }
+        assert!(lit);
+        assert_eq!(world.counts().lava, 1);
+        assert_eq!(world.counts().stone, 0);
+    }
+
+    #[test]
+    fn oil_floats_to_the_top_of_water() {
+        // the oil starts underneath, and the world's edges are the container
+        let mut world = World::new(10, 12);
+        for y in 0..6 {
+            for x in 0..10 {
+                let cell = if y < 3 {
+                    CellElement::Oil(PackedVelocity::ZERO)
+                } else {
+                    CellElement::Water(PackedVelocity::ZERO)
+                };
+                world.set_cell(&Coordinate::new(x, y), cell);
+            }
+        }
+
+        for _ in 0..300 {
+            world.update(&[]);
+        }
+
+        for y in 0..6 {
+            let expected = if y < 3 {
+                ElementKind::Water
+            } else {
+                ElementKind::Oil
+            };
+            for x in 0..10 {
+                let cell = world.get_cell(&Coordinate::new(x, y)).unwrap();
+                assert_eq!(cell.kind(), expected, "at ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn fire_lights_oil() {
+        let mut world = World::new(10, 10);
+        for x in 0..10 {
+            world.set_cell(
+                &Coordinate::new(x, 0),
+                CellElement::Oil(PackedVelocity::ZERO),
+            );
+        }
+        world.set_cell(&Coordinate::new(5, 1), CellElement::Fire(FIRE_LIFETIME));
+
+        world.update(&[]);
+        assert!(world.counts().oil < 10);
+        assert!(world.counts().fire > 1);
+    }
+
+    #[test]
+    fn emitter_pressure_varies_within_bounds() {
+        let mut emitter = Emitter::new(Coordinate::new(0, 0), 1.0, None);
+        emitter.direction = Vector2::new(1.0, 1.0).normalize();
+        emitter.pressure = 3.0;
+        emitter.pressure_variance = 0.5;
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let speeds: Vec<f32> = (0..100)
+            .map(|_| emitter.spawn_velocity(&mut rng).magnitude())
+            .collect();
+
+        for speed in speeds.iter() {
+            assert!((2.5 - 1e-4..=3.5 + 1e-4).contains(speed));
+        }
+        assert!(speeds.iter().any(|speed| (speed - speeds[0]).abs() > 0.1));
+    }
+
+    #[test]
+    fn emitter_spread_fans_cells_out_sideways() {
+        let mut emitter = Emitter::new(Coordinate::new(0, 0), 1.0, None);
+        emitter.pressure = 1.0;
+        emitter.spread = 0.5;
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let velocities: Vec<Vector2<f32>> =
+            (0..100).map(|_| emitter.spawn_velocity(&mut rng)).collect();
+
+        for velocity in velocities.iter() {
+            assert!((-0.5..=0.5).contains(&velocity.x));
+            assert_eq!(velocity.y, -1.0);
+        }
+        assert!(velocities.iter().any(|velocity| velocity.x < -0.2));
+        assert!(velocities.iter().any(|velocity| velocity.x > 0.2));
     }
 }
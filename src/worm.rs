@@ -1,16 +1,46 @@
-use nalgebra::Vector2;
+use std::collections::HashMap;
 
-use crate::world;
+use nalgebra::{Rotation2, Vector2};
+
+use crate::sprite::Sprite;
+use crate::world::{self, Coordinate, ElementKind, World};
+
+/// how far away, in cells, a worm notices sand
+const SEARCH_RADIUS: i64 = 40;
+/// radians per second a worm can turn toward its food
+const TURN_RATE: f32 = 1.5;
+/// hunger gained every second
+pub const HUNGER_RATE: f32 = 1.0;
+/// hunger a grain of sand takes away
+pub const NOURISHMENT: f32 = 2.0;
+/// worms starve once their hunger goes past this
+pub const STARVATION: f32 = 60.0;
+/// hunger past which a worm starts living off its own body
+pub const STARVING: f32 = 30.0;
+/// hunger it takes, once starving, to use up another segment
+pub const HUNGER_PER_SEGMENT: f32 = 5.0;
+/// grains a worm has to eat to grow another segment
+pub const GRAINS_PER_SEGMENT: u32 = 10;
+/// how close, in cells, another worm's segment can come before a worm steers away from it
+const SEPARATION_RADIUS: f32 = 25.0;
 
 pub struct Worm {
     pub head: WormSegment,
     pub segments: Vec<WormSegment>,
     pub segment_length: f32,
     pub speed: f32,
+    /// muted worms still move but don't push sand around
+    pub emits_force: bool,
+    /// grains of sand eaten so far
+    pub eaten: u32,
+    /// rises by `HUNGER_RATE` a second and falls with every grain eaten
+    pub hunger: f32,
+    /// where the worm steers when it's driven by hand; it ignores the sand while this is set
+    pub target: Option<Vector2<f32>>,
 }
 
 impl Worm {
-    /// creates a straight room facing direction; has to be normalized
+    /// creates a straight worm facing direction; has to be normalized
     pub fn new(
         segment_count: u8,
         mut position: Vector2<f32>,
@@ -22,7 +52,7 @@ impl Worm {
         let mut segments = Vec::<WormSegment>::new();
 
         for _i in 0..segment_count {
-            let next_position = (position - direction) * segment_length;
+            let next_position = position - direction * segment_length;
             segments.push(WormSegment(next_position));
             position = next_position;
         }
@@ -32,32 +62,289 @@ impl Worm {
             segment_length,
             segments,
             speed,
+            emits_force: true,
+            eaten: 0,
+            hunger: 0.0,
+            target: None,
         }
     }
 
+    /// Puts the head exactly at `position` and drags every segment along so it ends up exactly
+    /// `segment_length` behind the one before it, however far the head jumped
     pub fn move_to(&mut self, position: Vector2<f32>) {
+        let mut backward = self
+            .direction()
+            .map_or(Vector2::new(-1.0, 0.0), |direction| -direction);
         self.head.0 = position;
+
         let mut head = self.head;
         for segment in self.segments.iter_mut() {
-            let normal = (segment.0 - head.0).normalize();
-            segment.0 = (normal * self.segment_length) + head.0;
+            // a segment the head landed right on keeps trailing the way the body did
+            if let Some(normal) = (segment.0 - head.0).try_normalize(f32::EPSILON) {
+                backward = normal;
+            }
+            segment.0 = (backward * self.segment_length) + head.0;
             head = *segment;
         }
     }
 
     pub fn direction(&self) -> Option<Vector2<f32>> {
-        if let Some(neck) = self.segments.get(0) {
+        if let Some(neck) = self.segments.first() {
             return Some((self.head.0 - neck.0).normalize());
         }
         None
     }
 
-    pub fn step_ai(&mut self, delta: f32) {
-        // move straight for now
-        if let Some(direction) = self.direction() {
-            self.move_to(self.head.0 + direction * self.speed * delta);
+    /// Has the worm steer toward `target` instead of the sand, until `target` is cleared
+    pub fn set_target(&mut self, target: Vector2<f32>) {
+        self.target = Some(target);
+    }
+
+    /// Heads for its target, or the nearest sand without one, turning at most `TURN_RATE`
+    /// radians a second, and carries straight on when there's neither
+    pub fn step_ai(&mut self, world: &World, delta: f32) {
+        self.steer(world, Vector2::zeros(), delta);
+    }
+
+    /// Same as `step_ai`, but also turns toward `away`, which outweighs the pull of the sand
+    /// once it's longer than one
+    fn steer(&mut self, world: &World, away: Vector2<f32>, delta: f32) {
+        let starved = segments_starved(self.hunger);
+        self.hunger += HUNGER_RATE * delta;
+        for _ in starved..segments_starved(self.hunger) {
+            if self.segments.len() > 1 {
+                self.shrink();
+            }
         }
+
+        let Some(mut direction) = self.direction() else {
+            return;
+        };
+
+        let mut desired = direction;
+        if let Some(target) = self.target.or_else(|| self.nearest_sand(world)) {
+            if let Some(toward) = (target - self.head.0).try_normalize(f32::EPSILON) {
+                desired = toward;
+            }
+        }
+        // a worm coming straight at this one pushes it straight back, which gives no side to
+        // turn to, so it also dodges sideways like people passing in a corridor
+        let right = Vector2::new(direction.y, -direction.x);
+        let dodge = if away.dot(&right) >= 0.0 {
+            right
+        } else {
+            -right
+        };
+        let desired = desired + away + dodge * away.magnitude();
+        direction = turn_toward(direction, desired, TURN_RATE * delta);
+        self.move_to(self.head.0 + direction * self.speed * delta);
+    }
+
+    /// Adds a segment behind the tail, continuing the line of the last two
+    pub fn grow(&mut self) {
+        let tail = self.segments.last().copied().unwrap_or(self.head);
+        let ahead = match self.segments.len() {
+            0 => WormSegment(tail.0 + Vector2::x()),
+            1 => self.head,
+            length => self.segments[length - 2],
+        };
+
+        let backward = (tail.0 - ahead.0).normalize();
+        self.segments
+            .push(WormSegment(tail.0 + backward * self.segment_length));
     }
+
+    /// A `size` wide sprite for the head and each segment, facing the one ahead of it. Worm
+    /// positions are already centered on the world sprite, which has one pixel per cell, so
+    /// they're used as is
+    pub fn to_sprites(&self, size: f32) -> Vec<Sprite> {
+        let mut sprites = Vec::with_capacity(self.segments.len() + 1);
+        let mut ahead = self.head.0 + self.direction().unwrap_or_else(Vector2::x);
+        for segment in std::iter::once(&self.head).chain(self.segments.iter()) {
+            let facing = ahead - segment.0;
+            sprites.push(Sprite {
+                position: [segment.0.x, segment.0.y],
+                size: [size, size],
+                rotation: facing.y.atan2(facing.x),
+                color: Sprite::WHITE,
+            });
+            ahead = segment.0;
+        }
+        sprites
+    }
+
+    /// Drops the tail segment, if there's one left
+    pub fn shrink(&mut self) {
+        self.segments.pop();
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.hunger <= STARVATION
+    }
+
+    /// Eats the sand under the head; the rest of the body only passes through
+    pub fn eat(&mut self, world: &mut World) {
+        let Some(coordinate) = world.coordinate(&self.head.0) else {
+            return;
+        };
+
+        if world.get_cell(&coordinate).map(|cell| cell.kind()) == Some(ElementKind::Sand) {
+            world.set_cell(&coordinate, world::CellElement::Air);
+            self.eaten += 1;
+            self.hunger = (self.hunger - NOURISHMENT).max(0.0);
+            if self.eaten.is_multiple_of(GRAINS_PER_SEGMENT) {
+                self.grow();
+            }
+        }
+    }
+
+    /// Position of the closest sand within `SEARCH_RADIUS` of the head
+    fn nearest_sand(&self, world: &World) -> Option<Vector2<f32>> {
+        let center_x = (self.head.0.x + (world.width() / 2) as f32).round() as i64;
+        let center_y = (self.head.0.y + (world.height() / 2) as f32).round() as i64;
+
+        let mut nearest: Option<(f32, Vector2<f32>)> = None;
+        for y in center_y - SEARCH_RADIUS..=center_y + SEARCH_RADIUS {
+            for x in center_x - SEARCH_RADIUS..=center_x + SEARCH_RADIUS {
+                if x < 0 || y < 0 || x >= world.width() as i64 || y >= world.height() as i64 {
+                    continue;
+                }
+
+                let coordinate = Coordinate::new(x as u32, y as u32);
+                if world.get_cell(&coordinate).map(|cell| cell.kind()) != Some(ElementKind::Sand) {
+                    continue;
+                }
+
+                let position = world.position(&coordinate);
+                let distance_squared = (position - self.head.0).magnitude_squared();
+                if distance_squared <= (SEARCH_RADIUS * SEARCH_RADIUS) as f32
+                    && nearest.is_none_or(|(nearest, _)| distance_squared < nearest)
+                {
+                    nearest = Some((distance_squared, position));
+                }
+            }
+        }
+
+        nearest.map(|(_, position)| position)
+    }
+}
+
+/// How many segments a worm this hungry has lived off since it started starving
+fn segments_starved(hunger: f32) -> u32 {
+    ((hunger - STARVING) / HUNGER_PER_SEGMENT).max(0.0) as u32
+}
+
+/// Rotates the normalized `direction` toward `desired` by no more than `max_angle` radians
+fn turn_toward(direction: Vector2<f32>, desired: Vector2<f32>, max_angle: f32) -> Vector2<f32> {
+    if desired.magnitude_squared() < f32::EPSILON {
+        return direction;
+    }
+
+    let angle = Rotation2::rotation_between(&direction, &desired).angle();
+    Rotation2::new(angle.clamp(-max_angle, max_angle)) * direction
+}
+
+/// A population of worms that move and feed together
+#[derive(Default)]
+pub struct WormColony {
+    pub worms: Vec<Worm>,
+}
+
+impl WormColony {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a straight worm; `direction` has to be normalized
+    pub fn spawn(
+        &mut self,
+        position: Vector2<f32>,
+        direction: Vector2<f32>,
+        segment_count: u8,
+        segment_length: f32,
+        speed: f32,
+    ) {
+        self.worms.push(Worm::new(
+            segment_count,
+            position,
+            direction,
+            segment_length,
+            speed,
+        ));
+    }
+
+    /// Moves and feeds every worm, steering each away from the others, and returns the forces
+    /// they now exert for `World::update`
+    pub fn step_all(&mut self, world: &mut World, delta: f32) -> Vec<world::Force> {
+        // every worm steers away from where the others were at the start of the step, so the
+        // order they're stepped in doesn't matter
+        let grid = SegmentGrid::new(&self.worms);
+        for (index, worm) in self.worms.iter_mut().enumerate() {
+            let away = grid.separation(index, worm.head.0);
+            worm.steer(world, away, delta);
+            worm.eat(world);
+        }
+        collect_forces(&self.worms)
+    }
+}
+
+/// Every worm's head and segments sorted into square buckets `SEPARATION_RADIUS` wide, so a
+/// worm only has to look at the segments in the buckets around its head
+struct SegmentGrid {
+    buckets: HashMap<Vector2<i32>, Vec<(usize, WormSegment)>>,
+}
+
+impl SegmentGrid {
+    fn new(worms: &[Worm]) -> Self {
+        let mut buckets = HashMap::<Vector2<i32>, Vec<(usize, WormSegment)>>::new();
+        for (index, worm) in worms.iter().enumerate() {
+            for segment in std::iter::once(&worm.head).chain(worm.segments.iter()) {
+                buckets
+                    .entry(Self::bucket(segment.0))
+                    .or_default()
+                    .push((index, *segment));
+            }
+        }
+        Self { buckets }
+    }
+
+    fn bucket(position: Vector2<f32>) -> Vector2<i32> {
+        (position / SEPARATION_RADIUS).map(|value| value.floor() as i32)
+    }
+
+    /// Sum of pushes directly away from every segment of the other worms within
+    /// `SEPARATION_RADIUS` of `position`, each from nothing at the edge up to one when they
+    /// touch
+    fn separation(&self, worm: usize, position: Vector2<f32>) -> Vector2<f32> {
+        let center = Self::bucket(position);
+        let mut away = Vector2::zeros();
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let Some(segments) = self.buckets.get(&(center + Vector2::new(x, y))) else {
+                    continue;
+                };
+                for (_, segment) in segments.iter().filter(|(index, _)| *index != worm) {
+                    let difference = position - segment.0;
+                    let distance = difference.magnitude();
+                    if distance < SEPARATION_RADIUS && distance > f32::EPSILON {
+                        away += difference / distance * (1.0 - distance / SEPARATION_RADIUS);
+                    }
+                }
+            }
+        }
+        away
+    }
+}
+
+/// Gathers the forces of every worm that isn't muted
+pub fn collect_forces(worms: &[Worm]) -> Vec<world::Force> {
+    let mut forces = Vec::<world::Force>::new();
+    for worm in worms.iter().filter(|worm| worm.emits_force) {
+        for segment in worm.segments.iter() {
+            forces.push(segment.force());
+        }
+    }
+    forces
 }
 
 #[derive(Clone, Copy)]
@@ -68,8 +355,285 @@ impl WormSegment {
         world::Force {
             position: self.0,
             strength: 120.0,
+            kind: world::ForceKind::Attract,
             max_distance_squared: 900.0,
             min_distance_squared: 80.0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector2;
+
+    use super::{
+        collect_forces, Worm, WormColony, HUNGER_PER_SEGMENT, HUNGER_RATE, STARVATION, STARVING,
+    };
+    use crate::world::{CellElement, Coordinate, PackedVelocity, World};
+
+    fn worm(segment_count: u8) -> Worm {
+        Worm::new(
+            segment_count,
+            Vector2::zeros(),
+            Vector2::new(1.0, 0.0),
+            10.0,
+            4.0,
+        )
+    }
+
+    #[test]
+    fn new_worms_are_evenly_spaced() {
+        let worm = Worm::new(
+            5,
+            Vector2::new(30.0, 30.0),
+            Vector2::new(1.0, 1.0).normalize(),
+            10.0,
+            4.0,
+        );
+
+        let mut ahead = worm.head;
+        for segment in worm.segments.iter() {
+            assert!(((ahead.0 - segment.0).norm() - worm.segment_length).abs() < 1e-4);
+            ahead = *segment;
+        }
+        // trailing straight behind the head
+        assert!(((worm.segments[4].0 - worm.head.0).norm() - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn turns_toward_nearby_sand() {
+        let mut world = World::new(100, 100);
+        let grain = Coordinate::new(70, 50);
+        world.set_cell(&grain, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+
+        // heading up with the grain off to the right
+        let mut worm = Worm::new(3, Vector2::zeros(), Vector2::new(0.0, 1.0), 5.0, 4.0);
+        for _ in 0..5 {
+            worm.step_ai(&world, 0.1);
+        }
+        assert!(worm.direction().unwrap().x > 0.0);
+
+        // and straight on without any
+        world.clear();
+        let direction = worm.direction().unwrap();
+        worm.step_ai(&world, 0.1);
+        assert!((worm.direction().unwrap() - direction).norm() < 1e-4);
+    }
+
+    #[test]
+    fn eats_sand_under_the_head() {
+        let mut world = World::new(100, 100);
+        let grain = Coordinate::new(53, 50);
+        world.set_cell(&grain, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+        let behind = Coordinate::new(40, 50);
+        world.set_cell(&behind, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+
+        let mut worm = Worm::new(3, Vector2::zeros(), Vector2::new(1.0, 0.0), 5.0, 10.0);
+        for _ in 0..5 {
+            worm.step_ai(&world, 0.1);
+            worm.eat(&mut world);
+        }
+
+        assert_eq!(world.get_cell(&grain), Some(CellElement::Air));
+        assert_eq!(worm.eaten, 1);
+        // eating took away more hunger than the walk built up
+        assert!(worm.hunger < 5.0 * 0.1 * HUNGER_RATE);
+        // the tail lies on this one from the start
+        assert!(world.get_cell(&behind) != Some(CellElement::Air));
+    }
+
+    #[test]
+    fn keeps_spacing_after_large_moves() {
+        let mut worm = worm(5);
+        for step in 0..3 {
+            let position = match step {
+                0 => Vector2::new(500.0, -300.0),
+                1 => Vector2::new(-40.0, 2.0),
+                // right onto the first segment
+                _ => worm.segments[0].0,
+            };
+            worm.move_to(position);
+            assert_eq!(worm.head.0, position);
+
+            let mut ahead = worm.head;
+            for segment in worm.segments.iter() {
+                assert!(((ahead.0 - segment.0).norm() - worm.segment_length).abs() < 1e-3);
+                ahead = *segment;
+            }
+        }
+    }
+
+    #[test]
+    fn grows_and_shrinks_at_the_tail() {
+        let mut worm = worm(2);
+        worm.move_to(Vector2::new(3.0, 4.0));
+        let tail = worm.segments[1].0;
+        let backward = (tail - worm.segments[0].0).normalize();
+
+        worm.grow();
+        assert_eq!(worm.segments.len(), 3);
+        assert!((worm.segments[2].0 - (tail + backward * worm.segment_length)).norm() < 1e-4);
+
+        worm.shrink();
+        worm.shrink();
+        assert_eq!(worm.segments.len(), 1);
+        assert!(((worm.segments[0].0 - worm.head.0).norm() - worm.segment_length).abs() < 1e-4);
+
+        worm.shrink();
+        worm.shrink();
+        assert!(worm.segments.is_empty());
+        worm.grow();
+        assert!(((worm.segments[0].0 - worm.head.0).norm() - worm.segment_length).abs() < 1e-4);
+    }
+
+    #[test]
+    fn starves_without_food() {
+        let world = World::new(100, 100);
+        let mut worm = Worm::new(3, Vector2::zeros(), Vector2::new(1.0, 0.0), 5.0, 1.0);
+
+        let steps = (STARVATION / HUNGER_RATE) as usize;
+        for _ in 0..steps {
+            worm.step_ai(&world, 1.0);
+        }
+        assert!(worm.is_alive());
+
+        worm.step_ai(&world, 1.0);
+        assert!(!worm.is_alive());
+    }
+
+    #[test]
+    fn starving_worms_shrink_down_to_one_segment() {
+        let world = World::new(100, 100);
+        let mut worm = Worm::new(5, Vector2::zeros(), Vector2::new(1.0, 0.0), 5.0, 1.0);
+
+        for _ in 0..(STARVING / HUNGER_RATE) as usize {
+            worm.step_ai(&world, 1.0);
+        }
+        assert_eq!(worm.segments.len(), 5);
+
+        for _ in 0..(HUNGER_PER_SEGMENT / HUNGER_RATE) as usize {
+            worm.step_ai(&world, 1.0);
+        }
+        assert_eq!(worm.segments.len(), 4);
+
+        for _ in 0..((STARVATION - STARVING) / HUNGER_RATE) as usize {
+            worm.step_ai(&world, 1.0);
+        }
+        assert_eq!(worm.segments.len(), 1);
+    }
+
+    #[test]
+    fn colony_collects_a_force_per_segment() {
+        let mut world = World::new(100, 100);
+        let mut colony = WormColony::new();
+        colony.spawn(Vector2::zeros(), Vector2::new(1.0, 0.0), 3, 5.0, 4.0);
+        colony.spawn(
+            Vector2::new(10.0, 10.0),
+            Vector2::new(0.0, 1.0),
+            5,
+            2.0,
+            1.0,
+        );
+        colony.spawn(
+            Vector2::new(-20.0, 0.0),
+            Vector2::new(0.0, -1.0),
+            7,
+            10.0,
+            8.0,
+        );
+
+        let forces = colony.step_all(&mut world, 0.1);
+        assert_eq!(forces.len(), 3 + 5 + 7);
+        assert_eq!(forces[8].position, colony.worms[2].segments[0].0);
+    }
+
+    #[test]
+    fn worms_on_a_collision_course_pass_each_other() {
+        let mut world = World::new(300, 300);
+        let mut colony = WormColony::new();
+        colony.spawn(
+            Vector2::new(-60.0, 0.0),
+            Vector2::new(1.0, 0.0),
+            4,
+            5.0,
+            4.0,
+        );
+        colony.spawn(
+            Vector2::new(60.0, 0.0),
+            Vector2::new(-1.0, 0.0),
+            4,
+            5.0,
+            4.0,
+        );
+
+        let closest = |colony: &WormColony| {
+            let body = |worm: &Worm| {
+                std::iter::once(worm.head.0)
+                    .chain(worm.segments.iter().map(|segment| segment.0))
+                    .collect::<Vec<_>>()
+            };
+            let (first, second) = (body(&colony.worms[0]), body(&colony.worms[1]));
+            first
+                .iter()
+                .flat_map(|a| second.iter().map(move |b| (a - b).magnitude()))
+                .fold(f32::INFINITY, f32::min)
+        };
+
+        let mut nearest = f32::INFINITY;
+        for _ in 0..300 {
+            colony.step_all(&mut world, 0.1);
+            nearest = nearest.min(closest(&colony));
+        }
+        // they did get past each other, which without steering would have meant running
+        // straight through
+        assert!(colony.worms[0].head.0.x > colony.worms[1].head.0.x);
+        assert!(nearest > 5.0, "came within {nearest} cells");
+    }
+
+    #[test]
+    fn sprites_cover_the_head_and_every_segment() {
+        let worm = Worm::new(7, Vector2::new(0.0, 10.0), Vector2::new(0.0, 1.0), 5.0, 1.0);
+
+        let sprites = worm.to_sprites(4.0);
+        assert_eq!(sprites.len(), 8);
+        assert_eq!(sprites[0].position, [0.0, 10.0]);
+        assert_eq!(sprites[0].size, [4.0, 4.0]);
+        // the body trails straight down from the head, so every sprite faces up
+        for sprite in sprites.iter() {
+            assert!((sprite.rotation - std::f32::consts::FRAC_PI_2).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn chases_its_target_instead_of_sand() {
+        let mut world = World::new(100, 100);
+        // sand ahead and to the right, target ahead and to the left
+        world.set_cell(
+            &Coordinate::new(80, 30),
+            CellElement::Sand(PackedVelocity::ZERO, None, 0),
+        );
+        let mut worm = worm(3);
+        let target = Vector2::new(30.0, 20.0);
+        worm.set_target(target);
+
+        let start = (worm.head.0 - target).magnitude();
+        for _ in 0..50 {
+            worm.step_ai(&world, 0.1);
+        }
+        assert!((worm.head.0 - target).magnitude() < start);
+        assert!(worm.head.0.y > 0.0);
+    }
+
+    #[test]
+    fn muted_worms_emit_no_forces() {
+        let mut worms = vec![worm(3), worm(5)];
+        assert_eq!(collect_forces(&worms).len(), 8);
+
+        worms[1].emits_force = false;
+        let forces = collect_forces(&worms);
+        assert_eq!(forces.len(), 3);
+        for (force, segment) in forces.iter().zip(worms[0].segments.iter()) {
+            assert_eq!(force.position, segment.0);
+        }
+    }
+}
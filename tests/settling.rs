@@ -0,0 +1,105 @@
+use sandboxed::world::{CellElement, Coordinate, ElementKind, PackedVelocity, World};
+
+/// Longest a scene may take to settle before the test gives up on it
+const MAX_TICKS: u32 = 5000;
+
+/// An empty world whose random choices are the same every run
+fn seeded_empty_world() -> World {
+    let mut world = World::with_seed(17);
+    world.clear();
+    world
+}
+
+/// Drops `grains` of sand one at a time from `source`, waiting whenever it's still occupied
+fn pour(world: &mut World, source: &Coordinate, grains: u32) {
+    let mut poured = 0;
+    for _ in 0..MAX_TICKS {
+        if poured == grains {
+            return;
+        }
+        if world.get_cell(source) == Some(CellElement::Air) {
+            world.set_cell(source, CellElement::Sand(PackedVelocity::ZERO, None, 0));
+            poured += 1;
+        }
+        world.update(&[]);
+    }
+    panic!("only poured {poured} of {grains} grains");
+}
+
+/// Updates until an update doesn't change a single cell, returning how many it took
+fn settle(world: &mut World) -> u32 {
+    for ticks in 1..=MAX_TICKS {
+        let mutations = world.mutations();
+        world.update(&[]);
+        if world.mutations() == mutations {
+            return ticks;
+        }
+    }
+    panic!("still changing after {MAX_TICKS} ticks");
+}
+
+fn is_sand(world: &World, x: u32, y: u32) -> bool {
+    world
+        .get_cell(&Coordinate::new(x, y))
+        .map(|cell| cell.kind())
+        == Some(ElementKind::Sand)
+}
+
+/// Number of grains stacked in column `x`, all of them from the floor up
+fn column_height(world: &World, x: u32) -> u32 {
+    let height = (0..world.height())
+        .take_while(|&y| is_sand(world, x, y))
+        .count() as u32;
+    assert!(
+        (height..world.height()).all(|y| !is_sand(world, x, y)),
+        "column {x} has a gap under some of its sand"
+    );
+    height
+}
+
+#[test]
+fn a_single_grain_lands_on_the_floor() {
+    let mut world = seeded_empty_world();
+    pour(&mut world, &Coordinate::new(150, 200), 1);
+    settle(&mut world);
+
+    assert_eq!(world.counts().sand, 1);
+    assert!(is_sand(&world, 150, 0));
+}
+
+#[test]
+fn poured_sand_settles_into_a_pile_on_the_floor() {
+    let grains = 400;
+    let mut world = seeded_empty_world();
+    pour(&mut world, &Coordinate::new(150, 200), grains);
+    settle(&mut world);
+
+    // nothing was lost or made on the way down
+    assert_eq!(world.counts().sand, grains as usize);
+    let heights: Vec<u32> = (0..world.width())
+        .map(|x| column_height(&world, x))
+        .collect();
+    assert_eq!(heights.iter().sum::<u32>(), grains);
+
+    // one pile, peaked under the source and sloping away on both sides
+    let peak = *heights.iter().max().unwrap();
+    assert!((140..=160).any(|x| heights[x] == peak));
+    let width = heights.iter().filter(|&&height| height > 0).count() as u32;
+    assert!(width > peak, "{peak} tall but only {width} wide");
+    assert!(peak > 1, "spread flat instead of piling up");
+    assert!(heights[..100].iter().all(|&height| height == 0));
+    assert!(heights[200..].iter().all(|&height| height == 0));
+}
+
+#[test]
+fn a_settled_pile_stays_put() {
+    let mut world = seeded_empty_world();
+    pour(&mut world, &Coordinate::new(150, 200), 100);
+    settle(&mut world);
+
+    let mutations = world.mutations();
+    for _ in 0..50 {
+        world.update(&[]);
+    }
+    assert_eq!(world.mutations(), mutations);
+}
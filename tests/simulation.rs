@@ -0,0 +1,37 @@
+use nalgebra::Vector2;
+use sandboxed::{
+    simulation::simulate,
+    world::{CellElement, Coordinate, ElementKind, PackedVelocity, World},
+    worm::WormColony,
+};
+
+#[test]
+fn simulates_without_a_window() {
+    let mut world = World::new(40, 40);
+    world.set_cell(
+        &Coordinate::new(20, 39),
+        CellElement::Sand(PackedVelocity::ZERO, None, 0),
+    );
+    let mut colony = WormColony::new();
+    // well above the world so its forces don't reach the sand
+    colony.spawn(
+        Vector2::new(0.0, 100.0),
+        Vector2::new(1.0, 0.0),
+        3,
+        5.0,
+        4.0,
+    );
+    let start = colony.worms[0].head.0;
+
+    simulate(&mut world, &mut colony, 100, 0.1);
+
+    assert_eq!(world.tick(), 100);
+    // the grain fell all the way down
+    assert!((0..world.width()).any(|x| {
+        world
+            .get_cell(&Coordinate::new(x, 0))
+            .map(|cell| cell.kind())
+            == Some(ElementKind::Sand)
+    }));
+    assert!(colony.worms[0].head.0 != start);
+}
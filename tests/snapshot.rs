@@ -0,0 +1,67 @@
+use image::RgbaImage;
+use sandboxed::world::{CellElement, Coordinate, ElementKind, PackedVelocity, World};
+
+/// Panics unless both worlds look exactly the same, pointing at the first pixel that doesn't
+/// match, in image coordinates
+fn assert_same_image(actual: &World, expected: &World) {
+    let (actual, expected): (RgbaImage, RgbaImage) =
+        (actual.to_rgba_image(), expected.to_rgba_image());
+    assert_eq!(actual.dimensions(), expected.dimensions(), "sizes differ");
+
+    let differing: Vec<_> = actual
+        .enumerate_pixels()
+        .filter(|(x, y, pixel)| expected.get_pixel(*x, *y) != *pixel)
+        .collect();
+    if let Some((x, y, pixel)) = differing.first() {
+        panic!(
+            "{} pixels differ, the first at ({x}, {y}) is {:?} instead of {:?}",
+            differing.len(),
+            pixel.0,
+            expected.get_pixel(*x, *y).0
+        );
+    }
+}
+
+fn fill_row(world: &mut World, y: u32, cell: CellElement) {
+    for x in 0..world.width() {
+        world.set_cell(&Coordinate::new(x, y), cell);
+    }
+}
+
+#[test]
+fn falling_layers_land_as_expected() {
+    let sand = CellElement::Sand(PackedVelocity::ZERO, None, 0);
+    let water = CellElement::Water(PackedVelocity::ZERO);
+
+    // rows as wide as the world can only fall straight down
+    let mut simulated = World::new(8, 6);
+    fill_row(&mut simulated, 4, sand);
+    fill_row(&mut simulated, 5, water);
+    for _ in 0..20 {
+        simulated.update(&[]);
+    }
+
+    let mut expected = World::new(8, 6);
+    fill_row(&mut expected, 0, sand);
+    fill_row(&mut expected, 1, water);
+
+    assert_same_image(&simulated, &expected);
+}
+
+#[test]
+fn snapshots_are_the_right_way_up() {
+    let mut world = World::new(3, 2);
+    world.set_cell(&Coordinate::new(0, 0), CellElement::Stone);
+
+    let image = world.to_rgba_image();
+    assert_eq!(image.dimensions(), (3, 2));
+    // the bottom left cell is the bottom left pixel, which comes last in the image's rows
+    assert_eq!(
+        image.get_pixel(0, 1).0,
+        world.element_color(ElementKind::Stone)
+    );
+    assert_eq!(
+        image.get_pixel(0, 0).0,
+        world.element_color(ElementKind::Air)
+    );
+}
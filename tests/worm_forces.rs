@@ -0,0 +1,74 @@
+use nalgebra::Vector2;
+use sandboxed::{
+    world::{CellElement, Coordinate, World},
+    worm::{collect_forces, Worm, WormSegment},
+};
+
+/// Height of the sand bed in `World::default`
+const SURFACE: u32 = 30;
+const TICKS: usize = 5;
+/// Cells this far past a force's outer radius can't have been reached by
+/// grains sliding into the gaps it opened
+const MARGIN: f32 = 10.0;
+
+fn snapshot(world: &World) -> Vec<CellElement> {
+    let mut cells = Vec::with_capacity((world.width() * world.height()) as usize);
+    for y in 0..world.height() {
+        for x in 0..world.width() {
+            cells.push(world.get_cell(&Coordinate::new(x, y)).unwrap());
+        }
+    }
+    cells
+}
+
+#[test]
+fn worm_stirs_only_nearby_sand() {
+    let mut world = World::default();
+    let hover = world.position(&Coordinate::new(0, SURFACE + 10)).y;
+    let mut worm = Worm::new(
+        2,
+        Vector2::new(0.0, hover),
+        Vector2::new(1.0, 0.0),
+        10.0,
+        0.0,
+    );
+    worm.segments = vec![
+        WormSegment(Vector2::new(-10.0, hover)),
+        WormSegment(Vector2::new(-20.0, hover)),
+    ];
+    let forces = collect_forces(&[worm]);
+    assert_eq!(forces.len(), 2);
+
+    let before = snapshot(&world);
+    for _ in 0..TICKS {
+        world.update(&forces);
+    }
+    let after = snapshot(&world);
+
+    let mut stirred = 0;
+    for y in 0..world.height() {
+        for x in 0..world.width() {
+            let index = (y * world.width() + x) as usize;
+            if before[index] == after[index] {
+                continue;
+            }
+
+            let nearest = forces
+                .iter()
+                .map(|force| {
+                    (force.position - world.position(&Coordinate::new(x, y))).norm_squared()
+                })
+                .fold(f32::INFINITY, f32::min);
+            let reach = forces[0].max_distance_squared.sqrt() + MARGIN;
+            assert!(
+                nearest <= reach * reach,
+                "cell ({x}, {y}) changed {} cells away from the worm",
+                nearest.sqrt()
+            );
+            if nearest <= forces[0].max_distance_squared {
+                stirred += 1;
+            }
+        }
+    }
+    assert!(stirred > 0, "the worm didn't disturb any sand");
+}